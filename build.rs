@@ -1,13 +1,43 @@
 use anyhow::*;
 use glob::glob;
-use std::fs::{read_to_string, write, create_dir_all};
-use std::path::PathBuf;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write as _;
+use std::fs::{read, read_to_string, write, create_dir_all};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+#[path = "build/reflect.rs"]
+mod reflect;
+
+/// Has to be increased when the way shaders are compiled changes, so cached SPIR-V is rebuilt.
+const CACHE_VERSION: u32 = 1;
+
+/// FNV-1a, stable across builds unlike the std hashers.
+fn hash(parts: &[&[u8]]) -> u64 {
+    let mut hash = 0xcbf29ce484222325u64;
+    for part in parts {
+        for byte in part.iter().chain(&[0xFF]) {
+            hash ^= *byte as u64;
+            hash = hash.wrapping_mul(0x100000001b3);
+        }
+    }
+    hash
+}
 
 struct ShaderData {
     src: String,
     src_path: PathBuf,
     spv_path: PathBuf,
+    extension: String,
     kind: shaderc::ShaderKind,
+    /// `wgpu::ShaderStage` bits
+    stage: u32,
+    /// Defines from a `// permutations: DEBUG_GRID PIXEL_ART` line. Every combination of them is compiled
+    /// into its own variant.
+    permutations: Vec<String>,
+    /// Covers the source and everything it includes.
+    hash: u64,
 }
 
 impl ShaderData {
@@ -16,15 +46,32 @@ impl ShaderData {
             .extension()
             .context("File has no extension")?
             .to_str()
-            .context("Extension cannot be converted to &str")?;
-        let kind = match extension {
-            "vert" => shaderc::ShaderKind::Vertex,
-            "frag" => shaderc::ShaderKind::Fragment,
-            "comp" => shaderc::ShaderKind::Compute,
+            .context("Extension cannot be converted to &str")?
+            .to_string();
+        let (kind, stage) = match extension.as_str() {
+            "vert" => (shaderc::ShaderKind::Vertex, 1),
+            "frag" => (shaderc::ShaderKind::Fragment, 2),
+            "comp" => (shaderc::ShaderKind::Compute, 4),
             _ => bail!("Unsupported shader: {}", src_path.display()),
         };
 
         let src = read_to_string(src_path.clone())?;
+        let permutations = src
+            .lines()
+            .filter_map(|line| line.trim().strip_prefix("// permutations:"))
+            .flat_map(|defines| defines.split_whitespace().map(str::to_string))
+            .collect();
+
+        let mut includes = BTreeMap::new();
+        find_includes(&src, &src_path, &mut includes);
+        let version = CACHE_VERSION.to_le_bytes();
+        let mut parts = vec![&version[..], src.as_bytes()];
+        for (path, content) in &includes {
+            // This tells cargo to rerun this script if an included header changes.
+            println!("cargo:rerun-if-changed={}", path.display());
+            parts.push(content.as_bytes());
+        }
+        let hash = hash(&parts);
 
         let mut spv_path = PathBuf::new();
         spv_path.push(std::env::var("OUT_DIR").unwrap());
@@ -35,9 +82,178 @@ impl ShaderData {
             src,
             src_path,
             spv_path,
+            extension,
             kind,
+            stage,
+            permutations,
+            hash,
         })
     }
+
+    /// The defines and output path of every variant. The first one has no defines and keeps the plain
+    /// `.spv` name, the others append their defines, e.g. `shader.frag.debug_grid.spv`.
+    fn variants(&self) -> Vec<(Vec<&str>, PathBuf)> {
+        (0..1u32 << self.permutations.len())
+            .map(|bits| {
+                let defines = self.permutations
+                    .iter()
+                    .enumerate()
+                    .filter(|(i, _)| bits & (1 << i) != 0)
+                    .map(|(_, define)| define.as_str())
+                    .collect::<Vec<_>>();
+                let suffix = defines.iter().map(|define| format!("{}.", define.to_lowercase())).collect::<String>();
+                (defines, self.spv_path.with_extension(format!("{}spv", suffix)))
+            })
+            .collect()
+    }
+}
+
+/// `#include "file"` is looked up next to the including file first, `#include <file>` and everything
+/// not found there relative to `src/`.
+fn resolve_include(requested: &str, include_type: shaderc::IncludeType, requesting: &str) -> Result<shaderc::ResolvedInclude, String> {
+    let src_dir = Path::new("./src");
+    let mut candidates = Vec::new();
+    if let shaderc::IncludeType::Relative = include_type {
+        candidates.push(Path::new(requesting).parent().unwrap_or(src_dir).join(requested));
+    }
+    candidates.push(src_dir.join(requested));
+    let path = candidates
+        .into_iter()
+        .find(|path| path.is_file())
+        .ok_or_else(|| format!("Can't find {} included from {}", requested, requesting))?;
+    let content = read_to_string(&path).map_err(|err| format!("Can't read {}: {}", path.display(), err))?;
+    Ok(shaderc::ResolvedInclude {
+        resolved_name: path.to_string_lossy().into_owned(),
+        content,
+    })
+}
+
+/// Everything `src` includes, directly or through other headers. Includes that can't be resolved are left
+/// for the compiler to report.
+fn find_includes(src: &str, src_path: &Path, found: &mut BTreeMap<PathBuf, String>) {
+    for line in src.lines() {
+        let requested = match line.trim().strip_prefix("#include") {
+            Some(requested) => requested.trim(),
+            None => continue,
+        };
+        let (requested, include_type) = if requested.starts_with('"') {
+            (requested.trim_matches('"'), shaderc::IncludeType::Relative)
+        } else {
+            (requested.trim_start_matches('<').trim_end_matches('>'), shaderc::IncludeType::Standard)
+        };
+        if let Ok(resolved) = resolve_include(requested, include_type, &src_path.to_string_lossy()) {
+            let path = PathBuf::from(resolved.resolved_name);
+            if !found.contains_key(&path) {
+                find_includes(&resolved.content, &path, found);
+                found.insert(path, resolved.content);
+            }
+        }
+    }
+}
+
+fn compile_options(defines: &[&str]) -> Result<shaderc::CompileOptions<'static>> {
+    let mut options = shaderc::CompileOptions::new().context("Unable to create compile options")?;
+    options.set_include_callback(|requested, include_type, requesting, _depth| resolve_include(requested, include_type, requesting));
+    for define in defines {
+        options.add_macro_definition(define, None);
+    }
+    Ok(options)
+}
+
+/// One permutation of a shader.
+struct Job<'a> {
+    shader: &'a ShaderData,
+    defines: Vec<&'a str>,
+    spv_path: PathBuf,
+}
+
+impl Job<'_> {
+
+    fn hash(&self) -> u64 {
+        hash(&[&self.shader.hash.to_le_bytes(), self.defines.join(" ").as_bytes()])
+    }
+
+    fn hash_path(&self) -> PathBuf {
+        self.spv_path.with_extension("spv.hash")
+    }
+
+    /// The SPIR-V from the last build, if nothing that went into it has changed since.
+    fn cached(&self) -> Option<Vec<u8>> {
+        let hash = read_to_string(self.hash_path()).ok()?;
+        if hash.trim() != format!("{:016x}", self.hash()) {
+            return None;
+        }
+        read(&self.spv_path).ok()
+    }
+
+    /// Errors are returned as the compiler output, which already points at file and line.
+    fn compile(&self, compiler: &mut Option<shaderc::Compiler>) -> Result<Vec<u8>, String> {
+        if let Some(spirv) = self.cached() {
+            return Ok(spirv);
+        }
+        if compiler.is_none() {
+            *compiler = Some(shaderc::Compiler::new().ok_or("Unable to create shader compiler")?);
+        }
+        let options = compile_options(&self.defines).map_err(|err| err.to_string())?;
+        let compiled = compiler.as_mut().unwrap()
+            .compile_into_spirv(
+                &self.shader.src,
+                self.shader.kind,
+                &self.shader.src_path.to_str().unwrap(),
+                "main",
+                Some(&options),
+            )
+            .map_err(|err| {
+                let message = match err {
+                    shaderc::Error::CompilationError(_, message) => message,
+                    err => format!("{}: {}", self.shader.src_path.display(), err),
+                };
+                if self.defines.is_empty() {
+                    message
+                } else {
+                    format!("{}(compiled with {})", message, self.defines.join(" "))
+                }
+            })?;
+        for warning in compiled.get_warning_messages().lines() {
+            println!("cargo:warning={}", warning);
+        }
+
+        let spirv = compiled.as_binary_u8().to_vec();
+        let io_error = |err: std::io::Error| format!("Can't write {}: {}", self.spv_path.display(), err);
+        create_dir_all(self.spv_path.parent().unwrap()).map_err(io_error)?;
+        write(&self.spv_path, &spirv).map_err(io_error)?;
+        write(self.hash_path(), format!("{:016x}", self.hash())).map_err(io_error)?;
+        Ok(spirv)
+    }
+
+}
+
+/// Compiles the jobs on every core. A [shaderc::Compiler] is not thread safe, so every
+/// thread creates its own once it hits a job that isn't cached.
+fn compile_all(jobs: &[Job]) -> Vec<Result<Vec<u8>, String>> {
+    let next = AtomicUsize::new(0);
+    let results = Mutex::new((0..jobs.len()).map(|_| None).collect::<Vec<_>>());
+    let threads = std::thread::available_parallelism()
+        .map(|count| count.get())
+        .unwrap_or(1)
+        .min(jobs.len())
+        .max(1);
+    std::thread::scope(|scope| {
+        for _ in 0..threads {
+            scope.spawn(|| {
+                let mut compiler = None;
+                loop {
+                    let index = next.fetch_add(1, Ordering::Relaxed);
+                    if index >= jobs.len() {
+                        break;
+                    }
+                    let result = jobs[index].compile(&mut compiler);
+                    results.lock().unwrap()[index] = Some(result);
+                }
+            });
+        }
+    });
+    results.into_inner().unwrap().into_iter().map(Option::unwrap).collect()
 }
 
 fn main() -> Result<()> {
@@ -48,7 +264,6 @@ fn main() -> Result<()> {
         glob("./src/**/*.comp")?,
     ];
 
-    // This could be parallelized
     let shaders = shader_paths
         .iter_mut()
         .flatten()
@@ -57,31 +272,162 @@ fn main() -> Result<()> {
         .into_iter()
         .collect::<Result<Vec<_>>>()?;
 
-    let mut compiler = shaderc::Compiler::new().context("Unable to create shader compiler")?;
+    let wgsl_paths = glob("./src/**/*.wgsl")?.collect::<Result<Vec<_>, _>>()?;
+    let header_paths = glob("./src/**/*.glsl")?.collect::<Result<Vec<_>, _>>()?;
 
-    // This can't be parallelized. The [shaderc::Compiler] is not
-    // thread safe. Also, it creates a lot of resources. You could
-    // spawn multiple processes to handle this, but it would probably
-    // be better just to only compile shaders that have been changed
-    // recently.
+    // This tells cargo to rerun this script if a shader changes. Included headers are added by `ShaderData::load`,
+    // the directories of the shaders cover shaders that are added or removed.
+    let src_paths = shaders
+        .iter()
+        .map(|shader| &shader.src_path)
+        .chain(&wgsl_paths)
+        .chain(&header_paths)
+        .collect::<Vec<_>>();
+    let directories = src_paths.iter().filter_map(|path| path.parent()).collect::<BTreeSet<_>>();
+    for path in src_paths.iter().map(|path| path.as_path()).chain(directories) {
+        println!("cargo:rerun-if-changed={}", path.display());
+    }
 
-    for shader in shaders {
-        // This tells cargo to rerun this script if something in /src/ changes.
-        println!(
-            "cargo:rerun-if-changed={}",
-            shader.src_path.as_os_str().to_str().unwrap()
-        );
+    let jobs = shaders
+        .iter()
+        .flat_map(|shader| shader
+            .variants()
+            .into_iter()
+            .map(move |(defines, spv_path)| Job { shader, defines, spv_path }))
+        .collect::<Vec<_>>();
+    let results = compile_all(&jobs);
 
-        let compiled = compiler.compile_into_spirv(
-            &shader.src,
-            shader.kind,
-            &shader.src_path.to_str().unwrap(),
-            "main",
-            None,
-        )?;
-        create_dir_all(&shader.spv_path.parent().unwrap())?;
-        write(shader.spv_path, compiled.as_binary_u8())?;
+    let errors = results.iter().filter_map(|result| result.as_ref().err()).collect::<Vec<_>>();
+    if !errors.is_empty() {
+        for error in &errors {
+            eprintln!("{}", error.trim_end());
+        }
+        bail!("{} of {} shaders failed to compile", errors.len(), jobs.len());
     }
 
+    let mut programs = BTreeMap::new();
+    for (job, spirv) in jobs.iter().zip(results) {
+        let spirv = spirv.unwrap();
+        let shader = job.shader;
+
+        // Variants can add bindings, the program gets all of them
+        let bindings = reflect::reflect(&spirv)
+            .with_context(|| format!("Can't reflect {}", job.spv_path.display()))?;
+        let program = programs.entry(program_name(&shader.src_path)).or_insert_with(BTreeMap::new);
+        for binding in bindings {
+            let key = (binding.set, binding.binding);
+            match program.get_mut(&key) {
+                None => { program.insert(key, (binding, shader.stage, shader.src_path.clone())); }
+                Some((existing, stage, path)) => {
+                    ensure!(existing.ty == binding.ty,
+                        "Set {} binding {} is {:?} in {} but {:?} in {}",
+                        binding.set, binding.binding, existing.ty, path.display(), binding.ty, shader.src_path.display());
+                    *stage |= shader.stage;
+                }
+            }
+        }
+    }
+
+    let mut out_path = PathBuf::from(std::env::var("OUT_DIR").unwrap());
+    out_path.push("bindings.rs");
+    write(&out_path, generate_bindings(&programs)?)?;
+    write(out_path.with_file_name("shaders.rs"), generate_permutations(&shaders)?)?;
+
+    for src_path in wgsl_paths {
+        validate_wgsl(src_path)?;
+    }
+
+    Ok(())
+}
+
+/// WGSL is only checked here, wgpu translates it itself when the module is created. The source is copied to
+/// `OUT_DIR` so `include_wgsl_out!` finds it next to the compiled SPIR-V. Its bindings are not reflected.
+fn validate_wgsl(src_path: PathBuf) -> Result<()> {
+    let src = read_to_string(&src_path)?;
+    let module = naga::front::wgsl::parse_str(&src)
+        .map_err(|err| anyhow!("Can't parse {}: {:?}", src_path.display(), err))?;
+    naga::proc::Validator::new()
+        .validate(&module)
+        .map_err(|err| anyhow!("{} is invalid: {:?}", src_path.display(), err))?;
+
+    let mut out_path = PathBuf::from(std::env::var("OUT_DIR").unwrap());
+    out_path.push(&src_path);
+    create_dir_all(out_path.parent().unwrap())?;
+    write(out_path, src)?;
     Ok(())
-}
\ No newline at end of file
+}
+
+/// Shaders that share a name, like `shader.vert` and `shader.frag`, are linked into one program
+/// whose bind groups are the union of theirs.
+fn program_name(src_path: &Path) -> String {
+    src_path
+        .strip_prefix("./src")
+        .unwrap_or(src_path)
+        .with_extension("")
+        .to_string_lossy()
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_lowercase() } else { '_' })
+        .collect()
+}
+
+fn shader_stage(bits: u32) -> String {
+    match bits {
+        1 => "wgpu::ShaderStage::VERTEX".to_string(),
+        2 => "wgpu::ShaderStage::FRAGMENT".to_string(),
+        3 => "wgpu::ShaderStage::VERTEX_FRAGMENT".to_string(),
+        4 => "wgpu::ShaderStage::COMPUTE".to_string(),
+        bits => format!("wgpu::ShaderStage::from_bits_truncate({})", bits),
+    }
+}
+
+/// A module per program and a module per set inside of it, holding a constant for every binding
+/// and the `ENTRIES` of the bind group layout.
+fn generate_bindings(programs: &BTreeMap<String, BTreeMap<(u32, u32), (reflect::Binding, u32, PathBuf)>>) -> Result<String> {
+    let mut out = String::from("// Generated by build.rs from the compiled shaders\n");
+    for (program, bindings) in programs {
+        writeln!(out, "pub mod {} {{", program)?;
+        let mut sets = BTreeMap::new();
+        for ((set, _), entry) in bindings {
+            sets.entry(*set).or_insert_with(Vec::new).push(entry);
+        }
+        for (set, entries) in sets {
+            writeln!(out, "    pub mod set{} {{", set)?;
+            for (binding, _, _) in &entries {
+                let name = binding.name.to_uppercase();
+                let name = if name.starts_with(|c: char| c.is_ascii_digit()) { format!("_{}", name) } else { name };
+                writeln!(out, "        pub const {}: u32 = {};", name, binding.binding)?;
+            }
+            writeln!(out, "        pub const ENTRIES: &[wgpu::BindGroupLayoutEntry] = &[")?;
+            for (binding, stage, _) in &entries {
+                writeln!(out, "            wgpu::BindGroupLayoutEntry {{ binding: {}, visibility: {}, ty: {}, count: None }},",
+                    binding.binding, shader_stage(*stage), binding.ty.to_rust())?;
+            }
+            writeln!(out, "        ];")?;
+            writeln!(out, "    }}")?;
+        }
+        writeln!(out, "}}")?;
+    }
+    Ok(out)
+}
+
+/// A module per shader with permutations, holding a bit per define and `descriptor` to pick a variant.
+fn generate_permutations(shaders: &[ShaderData]) -> Result<String> {
+    let mut out = String::from("// Generated by build.rs from the shader permutations\n");
+    for shader in shaders.iter().filter(|shader| !shader.permutations.is_empty()) {
+        writeln!(out, "pub mod {}_{} {{", program_name(&shader.src_path), shader.extension)?;
+        for (i, define) in shader.permutations.iter().enumerate() {
+            writeln!(out, "    pub const {}: u32 = {};", define, 1 << i)?;
+        }
+        writeln!(out, "    pub const ALL: u32 = {};", (1 << shader.permutations.len()) - 1)?;
+        writeln!(out, "    pub fn descriptor(permutation: u32) -> wgpu::ShaderModuleDescriptor<'static> {{")?;
+        writeln!(out, "        match permutation & ALL {{")?;
+        for (bits, (_, spv_path)) in shader.variants().iter().enumerate() {
+            writeln!(out, "            {} => wgpu::include_spirv!({:?}),", bits, spv_path.to_string_lossy())?;
+        }
+        writeln!(out, "            _ => unreachable!(),")?;
+        writeln!(out, "        }}")?;
+        writeln!(out, "    }}")?;
+        writeln!(out, "}}")?;
+    }
+    Ok(out)
+}
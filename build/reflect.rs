@@ -0,0 +1,194 @@
+use anyhow::*;
+use std::collections::HashMap;
+
+// Only the parts of the SPIR-V spec that describe resource bindings
+const MAGIC: u32 = 0x0723_0203;
+const OP_NAME: u32 = 5;
+const OP_TYPE_INT: u32 = 21;
+const OP_TYPE_FLOAT: u32 = 22;
+const OP_TYPE_IMAGE: u32 = 25;
+const OP_TYPE_SAMPLER: u32 = 26;
+const OP_TYPE_SAMPLED_IMAGE: u32 = 27;
+const OP_TYPE_ARRAY: u32 = 28;
+const OP_TYPE_RUNTIME_ARRAY: u32 = 29;
+const OP_TYPE_STRUCT: u32 = 30;
+const OP_TYPE_POINTER: u32 = 32;
+const OP_VARIABLE: u32 = 59;
+const OP_DECORATE: u32 = 71;
+const OP_MEMBER_DECORATE: u32 = 72;
+
+const DECORATION_BUFFER_BLOCK: u32 = 3;
+const DECORATION_NON_WRITABLE: u32 = 24;
+const DECORATION_BINDING: u32 = 33;
+const DECORATION_DESCRIPTOR_SET: u32 = 34;
+
+const STORAGE_CLASS_UNIFORM_CONSTANT: u32 = 0;
+const STORAGE_CLASS_UNIFORM: u32 = 2;
+const STORAGE_CLASS_STORAGE_BUFFER: u32 = 12;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum BindingType {
+    Texture {
+        view_dimension: &'static str,
+        sample_type: &'static str,
+        multisampled: bool
+    },
+    Sampler,
+    UniformBuffer,
+    StorageBuffer {
+        read_only: bool
+    }
+}
+
+impl BindingType {
+
+    /// The matching `wgpu::BindingType` as Rust source.
+    pub fn to_rust(&self) -> String {
+        match self {
+            BindingType::Texture { view_dimension, sample_type, multisampled } => format!(
+                "wgpu::BindingType::Texture {{ multisampled: {}, view_dimension: wgpu::TextureViewDimension::{}, sample_type: wgpu::TextureSampleType::{} }}",
+                multisampled, view_dimension, sample_type),
+            BindingType::Sampler =>
+                "wgpu::BindingType::Sampler { comparison: false, filtering: true }".to_string(),
+            BindingType::UniformBuffer =>
+                "wgpu::BindingType::Buffer { ty: wgpu::BufferBindingType::Uniform, has_dynamic_offset: false, min_binding_size: None }".to_string(),
+            BindingType::StorageBuffer { read_only } => format!(
+                "wgpu::BindingType::Buffer {{ ty: wgpu::BufferBindingType::Storage {{ read_only: {} }}, has_dynamic_offset: false, min_binding_size: None }}",
+                read_only)
+        }
+    }
+
+}
+
+#[derive(Clone, Debug)]
+pub struct Binding {
+    pub set: u32,
+    pub binding: u32,
+    pub name: String,
+    pub ty: BindingType
+}
+
+enum Type {
+    Int { signed: bool },
+    Float,
+    Image { sampled_type: u32, dim: u32, depth: u32, arrayed: bool, multisampled: bool, sampled: u32 },
+    Sampler,
+    SampledImage,
+    Array,
+    Struct { members: u32 },
+    Pointer { pointee: u32 }
+}
+
+fn decode_string(words: &[u32]) -> String {
+    let bytes = words.iter().flat_map(|word| word.to_le_bytes()).take_while(|&byte| byte != 0).collect::<Vec<_>>();
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+/// Finds every descriptor set binding of a SPIR-V module.
+pub fn reflect(spirv: &[u8]) -> Result<Vec<Binding>> {
+    ensure!(spirv.len() % 4 == 0 && spirv.len() >= 20, "Not a SPIR-V module");
+    let mut words = spirv.chunks_exact(4).map(|bytes| u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])).collect::<Vec<_>>();
+    if words[0] == MAGIC.swap_bytes() {
+        words.iter_mut().for_each(|word| *word = word.swap_bytes());
+    }
+    ensure!(words[0] == MAGIC, "Not a SPIR-V module");
+
+    let mut names = HashMap::new();
+    let mut types = HashMap::new();
+    let mut variables = Vec::new();
+    let mut sets = HashMap::new();
+    let mut bindings = HashMap::new();
+    let mut buffer_blocks = Vec::new();
+    let mut non_writable = HashMap::<u32, u32>::new();
+
+    let mut offset = 5;
+    while offset < words.len() {
+        let count = (words[offset] >> 16) as usize;
+        let opcode = words[offset] & 0xFFFF;
+        ensure!(count > 0 && offset + count <= words.len(), "Malformed SPIR-V instruction at word {}", offset);
+        let op = &words[offset + 1..offset + count];
+        match opcode {
+            OP_NAME => { names.insert(op[0], decode_string(&op[1..])); }
+            OP_TYPE_INT => { types.insert(op[0], Type::Int { signed: op[2] != 0 }); }
+            OP_TYPE_FLOAT => { types.insert(op[0], Type::Float); }
+            OP_TYPE_IMAGE => { types.insert(op[0], Type::Image {
+                sampled_type: op[1],
+                dim: op[2],
+                depth: op[3],
+                arrayed: op[4] != 0,
+                multisampled: op[5] != 0,
+                sampled: op[6]
+            }); }
+            OP_TYPE_SAMPLER => { types.insert(op[0], Type::Sampler); }
+            OP_TYPE_SAMPLED_IMAGE => { types.insert(op[0], Type::SampledImage); }
+            OP_TYPE_ARRAY | OP_TYPE_RUNTIME_ARRAY => { types.insert(op[0], Type::Array); }
+            OP_TYPE_STRUCT => { types.insert(op[0], Type::Struct { members: op.len() as u32 - 1 }); }
+            OP_TYPE_POINTER => { types.insert(op[0], Type::Pointer { pointee: op[2] }); }
+            OP_VARIABLE => variables.push((op[1], op[0], op[2])),
+            OP_DECORATE => match op[1] {
+                DECORATION_DESCRIPTOR_SET => { sets.insert(op[0], op[2]); }
+                DECORATION_BINDING => { bindings.insert(op[0], op[2]); }
+                DECORATION_BUFFER_BLOCK => buffer_blocks.push(op[0]),
+                _ => {}
+            },
+            // glslang marks every member of a readonly buffer instead of the buffer itself
+            OP_MEMBER_DECORATE if op[2] == DECORATION_NON_WRITABLE => *non_writable.entry(op[0]).or_default() += 1,
+            _ => {}
+        }
+        offset += count;
+    }
+
+    let mut result = Vec::new();
+    for (id, pointer_type, storage_class) in variables {
+        let (set, binding) = match (sets.get(&id), bindings.get(&id)) {
+            (Some(&set), Some(&binding)) => (set, binding),
+            _ => continue
+        };
+        let type_id = match types.get(&pointer_type) {
+            Some(Type::Pointer { pointee }) => *pointee,
+            _ => bail!("Binding {} of set {} is not a pointer", binding, set)
+        };
+        // Uniform blocks without an instance name are named after their block
+        let name = names.get(&id)
+            .filter(|name| !name.is_empty())
+            .or_else(|| names.get(&type_id))
+            .cloned()
+            .unwrap_or_else(|| format!("binding_{}", binding));
+        let unsupported = |what: &str| anyhow!("{} ({}, set {}, binding {}) are not supported", what, name, set, binding);
+
+        let ty = match (storage_class, types.get(&type_id)) {
+            (STORAGE_CLASS_UNIFORM_CONSTANT, Some(Type::Sampler)) => BindingType::Sampler,
+            (STORAGE_CLASS_UNIFORM_CONSTANT, Some(&Type::Image { sampled_type, dim, depth, arrayed, multisampled, sampled })) => {
+                if sampled != 1 {
+                    return Err(unsupported("Storage images"));
+                }
+                let view_dimension = match (dim, arrayed) {
+                    (0, false) => "D1",
+                    (1, false) => "D2",
+                    (1, true) => "D2Array",
+                    (2, false) => "D3",
+                    (3, false) => "Cube",
+                    (3, true) => "CubeArray",
+                    _ => return Err(unsupported("Image dimensions other than 1d, 2d, 3d and cube"))
+                };
+                let sample_type = match types.get(&sampled_type) {
+                    _ if depth == 1 => "Depth",
+                    Some(Type::Float) => "Float { filterable: true }",
+                    Some(Type::Int { signed: true }) => "Sint",
+                    Some(Type::Int { signed: false }) => "Uint",
+                    _ => return Err(unsupported("Images of this sample type"))
+                };
+                BindingType::Texture { view_dimension, sample_type, multisampled }
+            }
+            (STORAGE_CLASS_UNIFORM_CONSTANT, Some(Type::SampledImage)) => return Err(unsupported("Combined image samplers")),
+            (_, Some(Type::Array)) => return Err(unsupported("Binding arrays")),
+            (STORAGE_CLASS_UNIFORM, Some(Type::Struct { .. })) if !buffer_blocks.contains(&type_id) => BindingType::UniformBuffer,
+            (STORAGE_CLASS_UNIFORM, Some(&Type::Struct { members })) | (STORAGE_CLASS_STORAGE_BUFFER, Some(&Type::Struct { members })) =>
+                BindingType::StorageBuffer { read_only: non_writable.get(&type_id).copied().unwrap_or(0) == members },
+            _ => return Err(unsupported("Bindings of this type"))
+        };
+        result.push(Binding { set, binding, name, ty });
+    }
+    result.sort_by_key(|binding| (binding.set, binding.binding));
+    Ok(result)
+}
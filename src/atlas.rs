@@ -0,0 +1,292 @@
+use std::collections::HashMap;
+use std::path::Path;
+use anyhow::*;
+use glam::*;
+use crate::texture::{TextureData, TextureKind, MipMaps, TILE_EXTRUSION};
+
+/// Where a sprite ended up in the atlas.
+#[derive(Copy, Clone, Debug)]
+pub struct AtlasRegion {
+    /// The array layer of the page.
+    pub page: u32,
+    /// Min and max corner of the trimmed sprite in the page, relative to the page without its extruded border.
+    pub uv: [f32; 4],
+    /// Size of the trimmed sprite in pixels.
+    pub size: UVec2
+}
+
+/// Sprites packed into equally sized pages, stored as the layers of one texture array.
+pub struct Atlas {
+    pub pages: TextureData<[u8; 4]>,
+    regions: HashMap<String, AtlasRegion>
+}
+
+impl Atlas {
+
+    /// Sprites are named after their file name without the extension.
+    pub fn get(&self, name: &str) -> Option<&AtlasRegion> {
+        self.regions.get(name)
+    }
+
+}
+
+pub struct AtlasBuilder {
+    page_size: u32,
+    /// Pixels around every sprite that repeat its edge texels, so linear filtering doesn't pick up its neighbours.
+    padding: u32,
+    trim: bool
+}
+
+impl Default for AtlasBuilder {
+    fn default() -> Self {
+        Self {
+            page_size: 1024,
+            padding: TILE_EXTRUSION,
+            trim: true
+        }
+    }
+}
+
+struct SpriteImage {
+    name: String,
+    image: image::RgbaImage,
+    /// Pixels trimmed off the left and top of the image.
+    offset: UVec2,
+    size: UVec2
+}
+
+impl AtlasBuilder {
+
+    /// Packs every png in `dir`, opening new pages when one is full.
+    pub fn build(&self, dir: &Path) -> Result<Atlas, Error> {
+        let mut sprites = Vec::new();
+        for entry in std::fs::read_dir(dir).with_context(|| format!("Can't read {}", dir.display()))? {
+            let path = entry?.path();
+            if path.extension().and_then(|ext| ext.to_str()) != Some("png") {
+                continue;
+            }
+            let name = path.file_stem().and_then(|stem| stem.to_str()).context("Invalid sprite name")?.to_string();
+            let image = image::open(&path).with_context(|| format!("Can't load {}", path.display()))?.to_rgba8();
+            sprites.push(self.load_sprite(name, image));
+        }
+        self.pack(sprites)
+    }
+
+    fn pack(&self, mut sprites: Vec<SpriteImage>) -> Result<Atlas, Error> {
+        // Placing the large sprites first leaves the small ones to fill the gaps
+        sprites.sort_by(|a, b| (b.size.y, b.size.x, &a.name).cmp(&(a.size.y, a.size.x, &b.name)));
+
+        let mut skylines: Vec<Skyline> = Vec::new();
+        let mut placements = Vec::new();
+        for sprite in &sprites {
+            let size = sprite.size + UVec2::splat(2 * self.padding);
+            ensure!(size.x <= self.page_size && size.y <= self.page_size, "{} doesn't fit on a {} pixel page", sprite.name, self.page_size);
+            let placement = skylines.iter_mut()
+                .enumerate()
+                .find_map(|(page, skyline)| skyline.insert(size).map(|position| (page, position)));
+            let (page, position) = match placement {
+                Some(placement) => placement,
+                None => {
+                    let mut skyline = Skyline::new(self.page_size);
+                    let position = skyline.insert(size).unwrap();
+                    skylines.push(skyline);
+                    (skylines.len() - 1, position)
+                }
+            };
+            placements.push((page as u32, position + UVec2::splat(self.padding)));
+        }
+
+        let border = TILE_EXTRUSION;
+        let layer_size = self.page_size + 2 * border;
+        let mut pages = TextureData::<[u8; 4]>::with_kind(TextureKind::D2Array, layer_size, layer_size, skylines.len().max(1) as u32, MipMaps::None);
+        let mut regions = HashMap::new();
+        let padding = self.padding as i32;
+        for (sprite, &(page, position)) in sprites.iter().zip(placements.iter()) {
+            // The padding repeats the closest edge texel of the sprite
+            for y in -padding..sprite.size.y as i32 + padding {
+                for x in -padding..sprite.size.x as i32 + padding {
+                    let source_x = x.clamp(0, sprite.size.x as i32 - 1) as u32 + sprite.offset.x;
+                    let source_y = y.clamp(0, sprite.size.y as i32 - 1) as u32 + sprite.offset.y;
+                    let pixel = sprite.image.get_pixel(source_x, source_y).0;
+                    let (target_x, target_y) = ((border + position.x) as i32 + x, (border + position.y) as i32 + y);
+                    *pages.get_pixel_mut(target_x as u32, target_y as u32, page, 0) = pixel;
+                }
+            }
+            let min = position.as_f32() / self.page_size as f32;
+            let max = (position + sprite.size).as_f32() / self.page_size as f32;
+            regions.insert(sprite.name.clone(), AtlasRegion {
+                page,
+                uv: [min.x, min.y, max.x, max.y],
+                size: sprite.size
+            });
+        }
+
+        Ok(Atlas { pages, regions })
+    }
+
+    fn load_sprite(&self, name: String, image: image::RgbaImage) -> SpriteImage {
+        let opaque = |x: u32, y: u32| image.get_pixel(x, y).0[3] != 0;
+        let (min, max) = if self.trim {
+            let columns = (0..image.width()).filter(|&x| (0..image.height()).any(|y| opaque(x, y))).collect::<Vec<_>>();
+            let rows = (0..image.height()).filter(|&y| (0..image.width()).any(|x| opaque(x, y))).collect::<Vec<_>>();
+            match (columns.first(), columns.last(), rows.first(), rows.last()) {
+                (Some(&x0), Some(&x1), Some(&y0), Some(&y1)) => (uvec2(x0, y0), uvec2(x1 + 1, y1 + 1)),
+                // Fully transparent sprites keep a single pixel so they still have a region
+                _ => (UVec2::ZERO, UVec2::ONE)
+            }
+        } else {
+            (UVec2::ZERO, uvec2(image.width(), image.height()))
+        };
+        SpriteImage {
+            name,
+            image,
+            offset: min,
+            size: max - min
+        }
+    }
+
+}
+
+/// Bottom left skyline packing. The skyline is the list of horizontal segments that form the top edge of
+/// everything placed so far, new rectangles go where their top edge ends up lowest.
+struct Skyline {
+    size: u32,
+    /// Start, height and width of every segment, ordered by start.
+    segments: Vec<(u32, u32, u32)>
+}
+
+impl Skyline {
+
+    fn new(size: u32) -> Self {
+        Self {
+            size,
+            segments: vec![(0, 0, size)]
+        }
+    }
+
+    /// The height a rectangle of `width` rests at when its left edge is at the start of segment `index`.
+    fn fit(&self, index: usize, width: u32) -> Option<u32> {
+        let x = self.segments[index].0;
+        if x + width > self.size {
+            return None;
+        }
+        let mut y = 0;
+        let mut remaining = width as i64;
+        for &(_, height, segment_width) in &self.segments[index..] {
+            if remaining <= 0 {
+                break;
+            }
+            y = y.max(height);
+            remaining -= segment_width as i64;
+        }
+        Some(y)
+    }
+
+    fn insert(&mut self, size: UVec2) -> Option<UVec2> {
+        let (index, y) = (0..self.segments.len())
+            .filter_map(|i| self.fit(i, size.x).map(|y| (i, y)))
+            .filter(|&(_, y)| y + size.y <= self.size)
+            .min_by_key(|&(i, y)| (y + size.y, self.segments[i].2))?;
+        let x = self.segments[index].0;
+
+        self.segments.insert(index, (x, y + size.y, size.x));
+        // Cut the segments now covered by the new one
+        let end = x + size.x;
+        let mut i = index + 1;
+        while i < self.segments.len() && self.segments[i].0 < end {
+            let (start, height, width) = self.segments[i];
+            if start + width <= end {
+                self.segments.remove(i);
+            } else {
+                self.segments[i] = (end, height, start + width - end);
+                i += 1;
+            }
+        }
+        // Merge neighbours of equal height
+        self.segments.dedup_by(|next, previous| {
+            if previous.1 == next.1 {
+                previous.2 += next.2;
+                true
+            } else {
+                false
+            }
+        });
+        Some(uvec2(x, y))
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn opaque_sprite(builder: &AtlasBuilder, name: &str, width: u32, height: u32) -> SpriteImage {
+        builder.load_sprite(name.to_string(), image::RgbaImage::from_pixel(width, height, image::Rgba([255, 0, 0, 255])))
+    }
+
+    /// The region including its padding in pixels of the page.
+    fn padded_rect(builder: &AtlasBuilder, region: &AtlasRegion) -> (UVec2, UVec2) {
+        let min = uvec2((region.uv[0] * builder.page_size as f32).round() as u32, (region.uv[1] * builder.page_size as f32).round() as u32);
+        (min - UVec2::splat(builder.padding), min + region.size + UVec2::splat(builder.padding))
+    }
+
+    #[test]
+    fn packs_without_overlaps() {
+        let builder = AtlasBuilder { page_size: 128, ..Default::default() };
+        let sprites = (0..80u32)
+            .map(|i| opaque_sprite(&builder, &format!("sprite{}", i), 4 + i * 7 % 29, 4 + i * 13 % 23))
+            .collect();
+        let atlas = builder.pack(sprites).unwrap();
+        assert_eq!(atlas.regions.len(), 80);
+        assert!(atlas.pages.layers() > 1);
+
+        let regions = atlas.regions.values().collect::<Vec<_>>();
+        for (i, a) in regions.iter().enumerate() {
+            let (min, max) = padded_rect(&builder, a);
+            assert!(max.x <= builder.page_size && max.y <= builder.page_size, "{:?} leaves the page", a);
+            assert!(a.page < atlas.pages.layers());
+            for b in &regions[i + 1..] {
+                let (other_min, other_max) = padded_rect(&builder, b);
+                let overlaps = min.x < other_max.x && other_min.x < max.x && min.y < other_max.y && other_min.y < max.y;
+                assert!(a.page != b.page || !overlaps, "{:?} overlaps {:?}", a, b);
+            }
+        }
+    }
+
+    #[test]
+    fn overflow_opens_a_new_page() {
+        let builder = AtlasBuilder { page_size: 64, ..Default::default() };
+        let sprites = vec![opaque_sprite(&builder, "a", 40, 40), opaque_sprite(&builder, "b", 40, 40), opaque_sprite(&builder, "c", 10, 10)];
+        let atlas = builder.pack(sprites).unwrap();
+        assert_eq!(atlas.pages.layers(), 2);
+        assert_ne!(atlas.get("a").unwrap().page, atlas.get("b").unwrap().page);
+        // The small sprite still fits next to the first large one
+        assert_eq!(atlas.get("c").unwrap().page, 0);
+
+        let too_large = vec![opaque_sprite(&builder, "large", 64, 10)];
+        assert!(builder.pack(too_large).is_err());
+    }
+
+    #[test]
+    fn trims_transparent_borders() {
+        let builder = AtlasBuilder::default();
+        let mut image = image::RgbaImage::new(8, 6);
+        image.put_pixel(2, 1, image::Rgba([255, 255, 255, 255]));
+        image.put_pixel(5, 3, image::Rgba([255, 255, 255, 1]));
+        let sprite = builder.load_sprite("sprite".to_string(), image.clone());
+        assert_eq!((sprite.offset, sprite.size), (uvec2(2, 1), uvec2(4, 3)));
+
+        let untrimmed = AtlasBuilder { trim: false, ..Default::default() }.load_sprite("sprite".to_string(), image);
+        assert_eq!((untrimmed.offset, untrimmed.size), (UVec2::ZERO, uvec2(8, 6)));
+    }
+
+    #[test]
+    fn keeps_a_pixel_of_fully_transparent_sprites() {
+        let builder = AtlasBuilder::default();
+        let sprite = builder.load_sprite("empty".to_string(), image::RgbaImage::new(5, 7));
+        assert_eq!((sprite.offset, sprite.size), (UVec2::ZERO, UVec2::ONE));
+        let atlas = builder.pack(vec![sprite]).unwrap();
+        assert_eq!(atlas.get("empty").unwrap().size, UVec2::ONE);
+    }
+
+}
@@ -0,0 +1,139 @@
+use std::collections::HashMap;
+use anyhow::*;
+use serde::Deserialize;
+use crate::catalog::Catalog;
+use crate::tilemap::TileGrid;
+
+/// Which neighbours pick the tile of a terrain cell.
+#[derive(Deserialize, Copy, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Neighbourhood {
+    /// The edges only, bits north = 1, east = 2, south = 4 and west = 8. Needs 16 tiles.
+    Four,
+    /// Edges and corners clockwise from north = 1 to north west = 128. A corner only counts
+    /// when both edges next to it do, which leaves the 47 masks of a blob tileset.
+    Eight
+}
+
+impl Neighbourhood {
+
+    /// Offsets of the neighbours in world space, in the order of their mask bits.
+    fn offsets(self) -> &'static [(i32, i32)] {
+        match self {
+            Neighbourhood::Four => &[(0, 1), (1, 0), (0, -1), (-1, 0)],
+            Neighbourhood::Eight => &[(0, 1), (1, 1), (1, 0), (1, -1), (0, -1), (-1, -1), (-1, 0), (-1, 1)]
+        }
+    }
+
+    fn mask(self, connects: impl Fn(i32, i32) -> bool) -> u8 {
+        let mut mask = self.offsets().iter()
+            .enumerate()
+            .filter(|(_, &(dx, dy))| connects(dx, dy))
+            .fold(0u8, |mask, (bit, _)| mask | 1 << bit);
+        if self == Neighbourhood::Eight {
+            for &corner in &[1, 3, 5, 7] {
+                let edges = 1 << (corner - 1) | 1 << ((corner + 1) % 8);
+                if mask & edges != edges {
+                    mask &= !(1 << corner);
+                }
+            }
+        }
+        mask
+    }
+
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct Terrain {
+    /// The value that paints this terrain in the grid layer.
+    pub value: String,
+    pub neighbours: Neighbourhood,
+    /// The tile for every mask of neighbours with the same terrain.
+    pub tiles: HashMap<u8, u32>
+}
+
+/// Turns an Ogmo grid layer of terrain types into tiles, keyed by the tileset label in the project.
+/// Cells outside the level count as the same terrain, so terrain continues past the level edges.
+#[derive(Deserialize, Clone, Debug, Default)]
+pub struct AutotileRules {
+    /// The grid layer with the terrain. Levels without it are left alone.
+    pub layer: String,
+    pub terrains: Vec<Terrain>
+}
+
+pub type TilesetAutotileRules = Catalog<AutotileRules>;
+
+impl AutotileRules {
+
+    /// The index of the terrain painted with `value`, `None` for empty cells.
+    pub fn terrain(&self, value: &str) -> Result<Option<usize>, Error> {
+        if value == "0" {
+            return Ok(None);
+        }
+        self.terrains.iter()
+            .position(|terrain| terrain.value == value)
+            .map(Some)
+            .with_context(|| format!("No autotile rules for terrain {:?} in layer {}", value, self.layer))
+    }
+
+    /// Places the tiles for `terrains`, one terrain index per cell of `grid` in world space,
+    /// over whatever the tile layer had in those cells.
+    pub fn apply(&self, grid: &mut TileGrid, terrains: &[Option<usize>]) -> Result<(), Error> {
+        let (width, height) = (grid.width() as i32, grid.height() as i32);
+        ensure!(terrains.len() == (width * height) as usize, "The terrain layer {} doesn't match the level size", self.layer);
+        let terrain_at = |x: i32, y: i32| if (0..width).contains(&x) && (0..height).contains(&y) {
+            Some(terrains[(x + y * width) as usize])
+        } else {
+            None
+        };
+
+        for y in 0..height {
+            for x in 0..width {
+                let index = match terrains[(x + y * width) as usize] {
+                    Some(index) => index,
+                    None => continue
+                };
+                let terrain = &self.terrains[index];
+                let mask = terrain.neighbours.mask(|dx, dy| terrain_at(x + dx, y + dy).map_or(true, |other| other == Some(index)));
+                let tile = terrain.tiles.get(&mask)
+                    .with_context(|| format!("Terrain {:?} has no tile for the neighbour mask {}", terrain.value, mask))?;
+                grid.set(x, y, Some(*tile));
+            }
+        }
+        Ok(())
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+    use std::path::Path;
+    use super::*;
+
+    /// Every combination of the eight neighbours, as the bits of `Neighbourhood::offsets`.
+    fn eight_neighbour_masks() -> HashSet<u8> {
+        let offsets = Neighbourhood::Eight.offsets();
+        (0..=255u8)
+            .map(|neighbours| Neighbourhood::Eight.mask(|dx, dy| {
+                let bit = offsets.iter().position(|&offset| offset == (dx, dy)).unwrap();
+                neighbours & 1 << bit != 0
+            }))
+            .collect()
+    }
+
+    #[test]
+    fn eight_neighbours_reduce_to_47_masks() {
+        assert_eq!(eight_neighbour_masks().len(), 47);
+    }
+
+    #[test]
+    fn default_rules_cover_every_mask() {
+        let rules = TilesetAutotileRules::load(&Path::new(env!("CARGO_MANIFEST_DIR")).join("assets/autotile.json")).unwrap();
+        let terrain = &rules.get("default").unwrap().terrains[0];
+        for mask in eight_neighbour_masks() {
+            assert!(terrain.tiles.contains_key(&mask), "No tile for mask {}", mask);
+        }
+    }
+
+}
@@ -0,0 +1,67 @@
+//! Bind group layout entries reflected from the compiled shaders by `build.rs`.
+//! Every shader program (`shader.vert` + `shader.frag` = `shader`) gets a module with a `setN` module per
+//! descriptor set, holding a constant per binding named after the GLSL variable and the layout `ENTRIES`.
+//! WGSL shaders are only validated, their layouts have to be written by hand.
+//! Reflection can't tell whether a uniform is bound with a dynamic offset or a texture is filterable, so the
+//! entries assume neither for buffers and filtering for float textures. Sets bound with the buffers of `buffer.rs`
+//! use their layouts instead, `assert_buffer_set!` checks that those match the shader.
+#![allow(dead_code)]
+
+include!(concat!(env!("OUT_DIR"), "/bindings.rs"));
+
+/// Whether the reflected `entries` are a single buffer of type `ty` at binding 0, the layout the buffers of
+/// `buffer.rs` create for themselves.
+pub const fn is_buffer_set(entries: &[wgpu::BindGroupLayoutEntry], ty: wgpu::BufferBindingType) -> bool {
+    if entries.len() != 1 || entries[0].binding != 0 {
+        return false;
+    }
+    match (&entries[0].ty, ty) {
+        (wgpu::BindingType::Buffer { ty: wgpu::BufferBindingType::Uniform, .. }, wgpu::BufferBindingType::Uniform) => true,
+        (wgpu::BindingType::Buffer { ty: wgpu::BufferBindingType::Storage { read_only }, .. }, wgpu::BufferBindingType::Storage { read_only: expected }) =>
+            *read_only == expected,
+        _ => false
+    }
+}
+
+/// Checks at compile time that a set bound with a buffer from `buffer.rs` is declared as such a buffer in the shader:
+/// `assert_buffer_set!(shader::set2, wgpu::BufferBindingType::Uniform);`
+#[macro_export]
+macro_rules! assert_buffer_set {
+    ($($set:ident)::+, $ty:expr) => {
+        const _: () = assert!($crate::bindings::is_buffer_set($crate::bindings::$($set)::+::ENTRIES, $ty),
+            concat!("`", stringify!($($set)::+), "` is not a single ", stringify!($ty), " at binding 0"));
+    }
+}
+
+/// A bind group layout that keeps its entries. wgpu layouts can't be compared, so the pipeline cache tells
+/// layouts apart by their entries.
+pub struct BindGroupLayout {
+    layout: wgpu::BindGroupLayout,
+    entries: Vec<wgpu::BindGroupLayoutEntry>
+}
+
+impl BindGroupLayout {
+
+    pub fn new(device: &wgpu::Device, label: &str, entries: &[wgpu::BindGroupLayoutEntry]) -> Self {
+        Self {
+            layout: device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: Some(label),
+                entries,
+            }),
+            entries: entries.to_vec()
+        }
+    }
+
+    pub fn entries(&self) -> &[wgpu::BindGroupLayoutEntry] {
+        &self.entries
+    }
+
+}
+
+impl std::ops::Deref for BindGroupLayout {
+    type Target = wgpu::BindGroupLayout;
+
+    fn deref(&self) -> &wgpu::BindGroupLayout {
+        &self.layout
+    }
+}
@@ -0,0 +1,56 @@
+// Renders one mip level from the next larger one. The bindings of wgsl shaders aren't reflected by build.rs,
+// the layout is declared in mipgen.rs
+
+// A single triangle that covers the whole viewport
+[[builtin(vertex_index)]]
+var<in> in_vertex_index: u32;
+[[builtin(position)]]
+var<out> out_position: vec4<f32>;
+
+[[stage(vertex)]]
+fn main() {
+    var corner: vec2<f32> = vec2<f32>(f32((in_vertex_index << 1u) & 2u), f32(in_vertex_index & 2u));
+    out_position = vec4<f32>(corner * 2.0 - vec2<f32>(1.0, 1.0), 0.0, 1.0);
+}
+
+[[group(0), binding(0)]]
+var t_source: texture_2d<f32>;
+
+[[builtin(frag_coord)]]
+var<in> in_frag_coord: vec4<f32>;
+[[location(0)]]
+var<out> out_color: vec4<f32>;
+
+// Averages the source texels under the target texel, weighted by how much of them it covers, so odd sizes
+// don't drop a row or column. Like the cpu filter the colors are weighted by their alpha, so transparent
+// texels don't darken their neighbours. Loading from and rendering to an srgb view averages in linear space
+[[stage(fragment)]]
+fn main() {
+    var source_size: vec2<i32> = textureDimensions(t_source, 0);
+    var scale: vec2<f32> = vec2<f32>(source_size) / vec2<f32>(max(source_size / vec2<i32>(2, 2), vec2<i32>(1, 1)));
+    var start: vec2<f32> = floor(in_frag_coord.xy) * scale;
+    var end: vec2<f32> = start + scale;
+    var sum: vec4<f32> = vec4<f32>(0.0, 0.0, 0.0, 0.0);
+    var y: i32 = i32(floor(start.y));
+    loop {
+        if (y >= i32(ceil(end.y))) {
+            break;
+        }
+        var x: i32 = i32(floor(start.x));
+        loop {
+            if (x >= i32(ceil(end.x))) {
+                break;
+            }
+            var coverage: vec2<f32> = min(end, vec2<f32>(f32(x + 1), f32(y + 1))) - max(start, vec2<f32>(f32(x), f32(y)));
+            var texel: vec4<f32> = textureLoad(t_source, vec2<i32>(x, y), 0);
+            sum = sum + vec4<f32>(texel.rgb * texel.a, texel.a) * coverage.x * coverage.y;
+            continuing {
+                x = x + 1;
+            }
+        }
+        continuing {
+            y = y + 1;
+        }
+    }
+    out_color = select(vec4<f32>(0.0, 0.0, 0.0, 0.0), vec4<f32>(sum.rgb / sum.a, sum.a / (scale.x * scale.y)), sum.a > 0.0);
+}
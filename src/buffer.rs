@@ -1,76 +1,343 @@
-pub struct UniformBuffer<T> where T: bytemuck::Pod{
+use std::marker::PhantomData;
+use glam::*;
+use crate::bindings::BindGroupLayout;
+
+/// Types whose memory layout matches the std140 layout of uniform blocks.
+/// Implemented for structs through `assert_std140!`, which checks every field offset at compile time.
+pub unsafe trait Std140: bytemuck::Pod {
+    const ALIGN: usize;
+}
+
+/// Types whose memory layout matches the std430 layout of storage blocks.
+pub unsafe trait Std430: bytemuck::Pod {
+    const ALIGN: usize;
+}
+
+macro_rules! impl_layout {
+    ($($ty:ty => $align:expr),*) => {
+        $(
+            unsafe impl Std140 for $ty { const ALIGN: usize = $align; }
+            unsafe impl Std430 for $ty { const ALIGN: usize = $align; }
+        )*
+    }
+}
+
+impl_layout!(f32 => 4, i32 => 4, u32 => 4, Vec2 => 8, IVec2 => 8, UVec2 => 8, Vec4 => 16, IVec4 => 16, UVec4 => 16, Mat4 => 16);
+
+/// std140 rounds the stride of array elements up to 16 bytes, so only elements that are a multiple of 16 bytes
+/// have the same layout as a rust array.
+unsafe impl<T: Std140, const N: usize> Std140 for [T; N] where [T; N]: bytemuck::Pod {
+    const ALIGN: usize = {
+        assert!(std::mem::size_of::<T>() % 16 == 0, "std140 array elements have to be a multiple of 16 bytes");
+        16
+    };
+}
+
+unsafe impl<T: Std430, const N: usize> Std430 for [T; N] where [T; N]: bytemuck::Pod {
+    const ALIGN: usize = T::ALIGN;
+}
+
+pub const fn align_to(offset: usize, align: usize) -> usize {
+    (offset + align - 1) / align * align
+}
+
+pub const fn max_align(aligns: &[usize]) -> usize {
+    let mut max = 1;
+    let mut i = 0;
+    while i < aligns.len() {
+        if aligns[i] > max {
+            max = aligns[i];
+        }
+        i += 1;
+    }
+    max
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! assert_layout {
+    ($layout:ident, $min_struct_align:expr, $ty:ty { $($field:ident : $field_ty:ty),* $(,)? }) => {
+        unsafe impl $crate::buffer::$layout for $ty {
+            const ALIGN: usize = $crate::buffer::max_align(&[$min_struct_align, $(<$field_ty as $crate::buffer::$layout>::ALIGN),*]);
+        }
+
+        const _: () = {
+            let mut offset = 0usize;
+            $(
+                // Makes sure the field has the listed type
+                { fn _field_type(value: &$ty) -> &$field_ty { &value.$field } }
+                offset = $crate::buffer::align_to(offset, <$field_ty as $crate::buffer::$layout>::ALIGN);
+                assert!(std::mem::offset_of!($ty, $field) == offset,
+                    concat!("`", stringify!($ty), "::", stringify!($field), "` is not at its ", stringify!($layout), " offset, add padding before it"));
+                offset += std::mem::size_of::<$field_ty>();
+            )*
+            assert!(std::mem::size_of::<$ty>() == $crate::buffer::align_to(offset, <$ty as $crate::buffer::$layout>::ALIGN),
+                concat!("The size of `", stringify!($ty), "` is not a multiple of its ", stringify!($layout), " alignment, add padding at the end"));
+        };
+    }
+}
+
+/// Checks at compile time that a `#[repr(C)]` struct can be used as a uniform block. All fields have to be listed
+/// in order with their types: `assert_std140!(Globals { camera: Mat4, tint: Vec4, time: f32, scale: f32, offset: Vec2 });`
+#[macro_export]
+macro_rules! assert_std140 {
+    ($($tokens:tt)*) => {
+        // Structs in uniform blocks are aligned to at least a vec4
+        $crate::assert_layout!(Std140, 16, $($tokens)*);
+    }
+}
+
+/// Like `assert_std140!` for structs in storage buffers.
+#[macro_export]
+macro_rules! assert_std430 {
+    ($($tokens:tt)*) => {
+        $crate::assert_layout!(Std430, 1, $($tokens)*);
+    }
+}
+
+/// How a buffer is bound. Every buffer gets its own bind group with the buffer at binding 0.
+#[derive(Copy, Clone, Debug)]
+pub struct BufferOptions<'a> {
+    pub label: &'a str,
+    pub visibility: wgpu::ShaderStage
+}
+
+impl Default for BufferOptions<'_> {
+    fn default() -> Self {
+        Self {
+            label: "Uniform Buffer",
+            visibility: wgpu::ShaderStage::VERTEX
+        }
+    }
+}
+
+impl<'a> BufferOptions<'a> {
+
+    pub fn label(self, label: &'a str) -> Self {
+        Self { label, ..self }
+    }
+
+    pub fn visibility(self, visibility: wgpu::ShaderStage) -> Self {
+        Self { visibility, ..self }
+    }
+
+}
+
+/// `binding_size` limits the binding to a part of the buffer, `None` binds all of it.
+fn create_bind_group(device: &wgpu::Device, options: &BufferOptions, buffer: &wgpu::Buffer, ty: wgpu::BufferBindingType,
+                     has_dynamic_offset: bool, binding_size: Option<wgpu::BufferSize>) -> (BindGroupLayout, wgpu::BindGroup) {
+    let bind_group_layout = BindGroupLayout::new(device, &format!("{} Bind Group Layout", options.label), &[
+        wgpu::BindGroupLayoutEntry {
+            binding: 0,
+            visibility: options.visibility,
+            ty: wgpu::BindingType::Buffer {
+                ty,
+                has_dynamic_offset,
+                min_binding_size: binding_size,
+            },
+            count: None,
+        }
+    ]);
+
+    let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+        layout: &bind_group_layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::Buffer {
+                    buffer,
+                    offset: 0,
+                    size: binding_size,
+                },
+            }
+        ],
+        label: Some(&format!("{} Bind Group", options.label)),
+    });
+
+    (bind_group_layout, bind_group)
+}
+
+pub struct UniformBuffer<T> where T: Std140{
     buffer: wgpu::Buffer,
-    bind_group_layout: wgpu::BindGroupLayout,
+    bind_group_layout: BindGroupLayout,
     bind_group: wgpu::BindGroup,
-    _marker: std::marker::PhantomData<T>
+    _marker: PhantomData<T>
 }
 
-impl<T> UniformBuffer<T>  where T: bytemuck::Pod {
-    pub fn new(device: &wgpu::Device) -> Self{
+impl<T> UniformBuffer<T>  where T: Std140 {
+    pub fn with_options(device: &wgpu::Device, options: BufferOptions) -> Self{
 
         let buffer= device.create_buffer(&wgpu::BufferDescriptor {
-            label: Some("Uniform Buffer"),
+            label: Some(options.label),
             size: std::mem::size_of::<T>() as u64,
             usage: wgpu::BufferUsage::UNIFORM | wgpu::BufferUsage::COPY_DST,
             mapped_at_creation: false
         });
 
-        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-            entries: &[
-                wgpu::BindGroupLayoutEntry {
-                    binding: 0,
-                    visibility: wgpu::ShaderStage::VERTEX,
-                    ty: wgpu::BindingType::Buffer {
-                        ty: wgpu::BufferBindingType::Uniform,
-                        has_dynamic_offset: false,
-                        min_binding_size: None,
-                    },
-                    count: None,
-                }
-            ],
-            label: Some("Uniform Bind Group Layout"),
-        });
+        let (bind_group_layout, bind_group) = create_bind_group(device, &options, &buffer, wgpu::BufferBindingType::Uniform, false, None);
+
+        Self{
+            buffer,
+            bind_group_layout,
+            bind_group,
+            _marker: PhantomData {}
+        }
+    }
+
+    pub fn layout(&self) -> &BindGroupLayout {
+        &self.bind_group_layout
+    }
+}
+
+/// A fixed number of `T` in one uniform buffer. Only one element is bound at a time, picked by a dynamic offset.
+pub struct UniformArrayBuffer<T> where T: Std140 {
+    buffer: wgpu::Buffer,
+    bind_group_layout: BindGroupLayout,
+    bind_group: wgpu::BindGroup,
+    len: u32,
+    _marker: PhantomData<T>
+}
+
+impl<T> UniformArrayBuffer<T> where T: Std140 {
+
+    /// Dynamic offsets have to be multiples of this.
+    const STRIDE: u64 = align_to(std::mem::size_of::<T>(), wgpu::BIND_BUFFER_ALIGNMENT as usize) as u64;
 
-        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            layout: &bind_group_layout,
-            entries: &[
-                wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: buffer.as_entire_binding(),
-                }
-            ],
-            label: Some("Uniform Bind Group"),
+    pub fn new(device: &wgpu::Device, len: u32, options: BufferOptions) -> Self {
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some(options.label),
+            size: Self::STRIDE * len.max(1) as u64,
+            usage: wgpu::BufferUsage::UNIFORM | wgpu::BufferUsage::COPY_DST,
+            mapped_at_creation: false
         });
 
-        Self{
+        let binding_size = wgpu::BufferSize::new(std::mem::size_of::<T>() as u64);
+        let (bind_group_layout, bind_group) = create_bind_group(device, &options, &buffer, wgpu::BufferBindingType::Uniform, true, binding_size);
+
+        Self {
             buffer,
             bind_group_layout,
             bind_group,
-            _marker: std::marker::PhantomData {}
+            len,
+            _marker: PhantomData {}
         }
     }
 
-    pub fn layout(&self) -> &wgpu::BindGroupLayout {
+    pub fn layout(&self) -> &BindGroupLayout {
         &self.bind_group_layout
     }
+
+    pub fn offset(&self, index: u32) -> wgpu::DynamicOffset {
+        debug_assert!(index < self.len);
+        (index as u64 * Self::STRIDE) as wgpu::DynamicOffset
+    }
+
+    /// Writes `values` starting at the first element.
+    pub fn write_all(&self, queue: &wgpu::Queue, values: &[T]) {
+        debug_assert!(values.len() <= self.len as usize);
+        let mut bytes = vec![0u8; Self::STRIDE as usize * values.len()];
+        for (chunk, value) in bytes.chunks_mut(Self::STRIDE as usize).zip(values) {
+            chunk[..std::mem::size_of::<T>()].copy_from_slice(bytemuck::bytes_of(value));
+        }
+        queue.write_buffer(&self.buffer, 0, &bytes);
+    }
+
 }
 
-pub trait UpdateUniformBuffer<T>  where T: bytemuck::Pod{
+/// Per instance data that is too large for a uniform buffer. Grows when more elements are written than fit.
+/// Growing replaces the bind group, so it has to be fetched again afterwards.
+pub struct StorageBuffer<T> where T: Std430 {
+    buffer: wgpu::Buffer,
+    bind_group_layout: BindGroupLayout,
+    bind_group: wgpu::BindGroup,
+    capacity: usize,
+    read_only: bool,
+    label: String,
+    visibility: wgpu::ShaderStage,
+    _marker: PhantomData<T>
+}
+
+impl<T> StorageBuffer<T> where T: Std430 {
+
+    pub fn new(device: &wgpu::Device, capacity: usize, read_only: bool, options: BufferOptions) -> Self {
+        let capacity = capacity.max(1);
+        let buffer = Self::create_buffer(device, capacity, options.label);
+        let (bind_group_layout, bind_group) = create_bind_group(device, &options, &buffer, wgpu::BufferBindingType::Storage { read_only }, false, None);
+        Self {
+            buffer,
+            bind_group_layout,
+            bind_group,
+            capacity,
+            read_only,
+            label: options.label.to_string(),
+            visibility: options.visibility,
+            _marker: PhantomData {}
+        }
+    }
+
+    fn create_buffer(device: &wgpu::Device, capacity: usize, label: &str) -> wgpu::Buffer {
+        device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some(label),
+            size: (capacity * std::mem::size_of::<T>()) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsage::STORAGE | wgpu::BufferUsage::COPY_DST,
+            mapped_at_creation: false
+        })
+    }
+
+    pub fn layout(&self) -> &BindGroupLayout {
+        &self.bind_group_layout
+    }
+
+    /// Replaces the contents with `values`.
+    pub fn write(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, values: &[T]) {
+        if values.len() > self.capacity {
+            self.capacity = values.len().next_power_of_two();
+            self.buffer = Self::create_buffer(device, self.capacity, &self.label);
+            let options = BufferOptions { label: &self.label, visibility: self.visibility };
+            let (_, bind_group) = create_bind_group(device, &options, &self.buffer, wgpu::BufferBindingType::Storage { read_only: self.read_only }, false, None);
+            self.bind_group = bind_group;
+        }
+        queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(values));
+    }
+
+}
+
+pub trait UpdateUniformBuffer<T>  where T: Std140{
     fn update_uniform_buffer(&self, buffer: &UniformBuffer<T>, value: &T);
 }
 
-impl<T> UpdateUniformBuffer<T> for wgpu::Queue where T: bytemuck::Pod{
+impl<T> UpdateUniformBuffer<T> for wgpu::Queue where T: Std140{
     fn update_uniform_buffer(&self, buffer: &UniformBuffer<T>, value: &T) {
         self.write_buffer(&buffer.buffer, 0, bytemuck::bytes_of(value));
     }
 }
 
-pub trait BindUniformBuffer<'a, 'b, T> where 'b: 'a, T: bytemuck::Pod {
+pub trait BindUniformBuffer<'a, 'b, T> where 'b: 'a, T: Std140 {
     fn set_uniform_buffer(&mut self, index: u32, buffer: &'b UniformBuffer<T>);
 }
 
-impl<'a, 'b, T> BindUniformBuffer<'a, 'b, T> for wgpu::RenderPass<'a>  where 'b: 'a, T: bytemuck::Pod {
+impl<'a, 'b, T> BindUniformBuffer<'a, 'b, T> for wgpu::RenderPass<'a>  where 'b: 'a, T: Std140 {
     fn set_uniform_buffer(&mut self, index: u32, buffer: &'b UniformBuffer<T>) {
         self.set_bind_group(index, &buffer.bind_group, &[]);
     }
-}
\ No newline at end of file
+}
+
+pub trait BindUniformArrayBuffer<'a, 'b, T> where 'b: 'a, T: Std140 {
+    fn set_uniform_array_buffer(&mut self, index: u32, buffer: &'b UniformArrayBuffer<T>, element: u32);
+}
+
+impl<'a, 'b, T> BindUniformArrayBuffer<'a, 'b, T> for wgpu::RenderPass<'a>  where 'b: 'a, T: Std140 {
+    fn set_uniform_array_buffer(&mut self, index: u32, buffer: &'b UniformArrayBuffer<T>, element: u32) {
+        self.set_bind_group(index, &buffer.bind_group, &[buffer.offset(element)]);
+    }
+}
+
+pub trait BindStorageBuffer<'a, 'b, T> where 'b: 'a, T: Std430 {
+    fn set_storage_buffer(&mut self, index: u32, buffer: &'b StorageBuffer<T>);
+}
+
+impl<'a, 'b, T> BindStorageBuffer<'a, 'b, T> for wgpu::RenderPass<'a>  where 'b: 'a, T: Std430 {
+    fn set_storage_buffer(&mut self, index: u32, buffer: &'b StorageBuffer<T>) {
+        self.set_bind_group(index, &buffer.bind_group, &[]);
+    }
+}
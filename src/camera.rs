@@ -1,4 +1,5 @@
 use glam::*;
+use crate::physics::Aabb;
 
 pub struct Camera {
     pub position: Vec2,
@@ -22,6 +23,15 @@ impl Camera {
         self.aspect = width as f32 / height as f32;
     }
 
+    /// The part of the world that is visible.
+    pub fn bounds(&self) -> Aabb {
+        let half_extent = vec2(self.scale * self.aspect, self.scale);
+        Aabb {
+            min: self.position - half_extent,
+            max: self.position + half_extent
+        }
+    }
+
     pub fn to_matrix(&self) -> Mat4 {
         Mat4::orthographic_rh(self.position.x - (self.scale * self.aspect),
                               self.position.x + (self.scale * self.aspect),
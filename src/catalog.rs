@@ -0,0 +1,21 @@
+use std::collections::HashMap;
+use std::path::Path;
+use anyhow::*;
+use serde::de::DeserializeOwned;
+
+/// Entity definitions loaded from a json file, looked up by the name of the Ogmo entity that places them.
+pub struct Catalog<T>(HashMap<String, T>);
+
+impl<T> Catalog<T> where T: DeserializeOwned {
+
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        let file = std::fs::File::open(path).with_context(|| format!("Can't open {}", path.display()))?;
+        Ok(Self(serde_json::from_reader(std::io::BufReader::new(file))
+            .with_context(|| format!("Can't parse {}", path.display()))?))
+    }
+
+    pub fn get(&self, name: &str) -> Option<&T> {
+        self.0.get(name)
+    }
+
+}
\ No newline at end of file
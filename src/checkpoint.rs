@@ -0,0 +1,33 @@
+use glam::*;
+use crate::physics::Aabb;
+
+const SIZE: Vec2 = const_vec2!([0.3, 1.5]);
+
+pub const INACTIVE_COLOR: [f32; 4] = [0.5, 0.5, 0.55, 1.0];
+pub const ACTIVE_COLOR: [f32; 4] = [0.3, 0.9, 0.4, 1.0];
+
+/// A flag pole that becomes the respawn point once the player touches it.
+#[derive(Clone)]
+pub struct Checkpoint {
+    pub spawn: Vec2,
+    pub active: bool
+}
+
+impl Checkpoint {
+
+    pub fn new(spawn: Vec2) -> Self {
+        Self {
+            spawn,
+            active: false
+        }
+    }
+
+    pub fn aabb(&self) -> Aabb {
+        Aabb::new(self.spawn - vec2(SIZE.x * 0.5, 0.0), SIZE)
+    }
+
+    pub fn color(&self) -> [f32; 4] {
+        if self.active { ACTIVE_COLOR } else { INACTIVE_COLOR }
+    }
+
+}
@@ -0,0 +1,275 @@
+use std::rc::Rc;
+use anyhow::*;
+use glam::*;
+use crate::framework::Display;
+use crate::buffer::{UniformBuffer, StorageBuffer, BufferOptions, BindUniformBuffer, BindStorageBuffer, UpdateUniformBuffer};
+use crate::texture::{TextureData, MipMaps};
+use crate::tilemap::{TileGrid, TileAnimation};
+use crate::physics::Aabb;
+use crate::camera::Camera;
+use crate::mesh::Mesh;
+use crate::pipeline::{PipelineBuilder, BlendMode};
+use crate::bindings::{self, BindGroupLayout};
+use crate::{shaders, include_spirv_out, impl_vertex, assert_buffer_set};
+
+/// Width and height of a chunk in tiles.
+pub const CHUNK_SIZE: u32 = 64;
+
+/// The low bits of a placement hold the tile id + 1, the bits above the transform of the tile.
+/// Has to match `PLACEMENT_ID_BITS` in `shader.frag`.
+const PLACEMENT_ID_BITS: u32 = 13;
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct Vertex {
+    position: Vec2,
+    tex_coords: Vec2,
+}
+
+impl_vertex!(Vertex { position: Float2, tex_coords: Float2 });
+
+assert_buffer_set!(shader::set2, wgpu::BufferBindingType::Uniform);
+assert_buffer_set!(shader::set3, wgpu::BufferBindingType::Storage { read_only: true });
+
+/// A part of the level with its own placement texture, drawn as a single quad.
+struct Chunk {
+    bounds: Aabb,
+    quad: Mesh<Vertex, u16>,
+    bind_group: wgpu::BindGroup
+}
+
+/// The tile layer of a level split into chunks of `CHUNK_SIZE` tiles, so the size of a level isn't
+/// limited by the maximum texture size. Only chunks that overlap the camera are drawn.
+pub struct ChunkedTilemap {
+    render_pipeline: Rc<wgpu::RenderPipeline>,
+    bind_group_layout: BindGroupLayout,
+    /// The `shaders::shader_frag` defines `render_pipeline` was built with.
+    permutation: u32,
+    chunks: Vec<Chunk>,
+    /// Seconds since the level started, drives the tile animations.
+    time: f64,
+    /// The time in whole milliseconds.
+    time_buffer: UniformBuffer<u32>,
+    animation_buffer: StorageBuffer<UVec4>
+}
+
+/// Lays out `animations` the way `shader.frag` reads them: a header with the number of tiles that have an entry,
+/// an entry per tile up to the last animated one and the frames of all animations.
+/// Every tile and frame has to be one of the `tile_count` layers of the tileset.
+fn animation_table(animations: &[TileAnimation], tile_count: u32) -> Result<Vec<UVec4>, Error> {
+    let entry_count = animations.iter().map(|animation| animation.tile + 1).max().unwrap_or(0);
+    let mut entries = vec![UVec4::ZERO; 1 + entry_count as usize];
+    entries[0].x = entry_count;
+    for animation in animations {
+        ensure!(!animation.frames.is_empty(), "The animation of tile {} has no frames", animation.tile);
+        ensure!(animation.tile < tile_count, "Tile {} is animated but the tileset only has {} tiles", animation.tile, tile_count);
+        if let Some(frame) = animation.frames.iter().find(|&&frame| frame >= tile_count) {
+            bail!("The animation of tile {} shows tile {} but the tileset only has {} tiles", animation.tile, frame, tile_count);
+        }
+        let frame_duration = (animation.frame_duration * 1000.0).round() as u32;
+        ensure!(frame_duration > 0, "The animation of tile {} has a frame duration of {}", animation.tile, animation.frame_duration);
+        entries[1 + animation.tile as usize] = uvec4(entries.len() as u32, animation.frames.len() as u32, frame_duration, 0);
+        entries.extend(animation.frames.iter().map(|&frame| uvec4(frame, 0, 0, 0)));
+    }
+    Ok(entries)
+}
+
+impl ChunkedTilemap {
+
+    /// `tile_count` is the number of layers of `tileset_view`.
+    pub fn new(display: &Display, grid: &TileGrid, animations: &[TileAnimation], tile_count: u32, tileset_view: &wgpu::TextureView,
+               camera_layout: &BindGroupLayout) -> Result<Self, Error> {
+        let sampler = display.device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+
+        let bind_group_layout = BindGroupLayout::new(&display.device, "texture_bind_group_layout", bindings::shader::set0::ENTRIES);
+
+        let animations = animation_table(animations, tile_count)?;
+        let mut animation_buffer = StorageBuffer::<UVec4>::new(&display.device, animations.len(), true, BufferOptions::default()
+            .label("Tile Animations")
+            .visibility(bindings::shader::set3::ENTRIES[0].visibility));
+        animation_buffer.write(&display.device, &display.queue, &animations);
+        let time_buffer = UniformBuffer::<u32>::with_options(&display.device, BufferOptions::default()
+            .label("Time Buffer")
+            .visibility(bindings::shader::set2::ENTRIES[0].visibility));
+
+        let mut chunks = Vec::new();
+        for chunk_y in (0..grid.height()).step_by(CHUNK_SIZE as usize) {
+            for chunk_x in (0..grid.width()).step_by(CHUNK_SIZE as usize) {
+                let origin = uvec2(chunk_x, chunk_y);
+                let size = uvec2(CHUNK_SIZE.min(grid.width() - chunk_x), CHUNK_SIZE.min(grid.height() - chunk_y));
+                chunks.push(Self::create_chunk(display, grid, origin, size, tileset_view, &sampler, &bind_group_layout)?);
+            }
+        }
+        log::info!("Split the {}x{} tilemap into {} chunks", grid.width(), grid.height(), chunks.len());
+
+        Ok(Self {
+            render_pipeline: Self::create_render_pipeline(display, &bind_group_layout, camera_layout, time_buffer.layout(), animation_buffer.layout(), 0),
+            bind_group_layout,
+            permutation: 0,
+            chunks,
+            time: 0.0,
+            time_buffer,
+            animation_buffer
+        })
+    }
+
+    fn create_chunk(display: &Display, grid: &TileGrid, origin: UVec2, size: UVec2, tileset_view: &wgpu::TextureView,
+                    sampler: &wgpu::Sampler, bind_group_layout: &wgpu::BindGroupLayout) -> Result<Chunk, Error> {
+        // The rows of the placement texture go down, the rows of the grid up
+        let mut pt_data = TextureData::<u16>::new(size.x, size.y, 1, MipMaps::None);
+        for y in 0..size.y {
+            for x in 0..size.x {
+                let (grid_x, grid_y) = ((origin.x + x) as i32, (origin.y + size.y - 1 - y) as i32);
+                if let Some(id) = grid.get(grid_x, grid_y) {
+                    ensure!(id + 1 < 1 << PLACEMENT_ID_BITS, "Tile {} is beyond the {} tiles a placement can address", id, (1 << PLACEMENT_ID_BITS) - 1);
+                    let transform = grid.transform(grid_x, grid_y) as u32;
+                    *pt_data.get_pixel_mut(x, y, 0, 0) = ((1 + id) | transform << PLACEMENT_ID_BITS) as u16;
+                }
+            }
+        }
+        let placement_texture = pt_data.to_texture(&display.device, &display.queue, wgpu::TextureFormat::R16Uint, wgpu::TextureUsage::SAMPLED | wgpu::TextureUsage::COPY_DST)?;
+        let placement_texture_view = pt_data.create_view(&placement_texture);
+
+        let bind_group = display.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: bindings::shader::set0::T_DIFFUSE_ARRAY,
+                    resource: wgpu::BindingResource::TextureView(tileset_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: bindings::shader::set0::T_PLACEMENT,
+                    resource: wgpu::BindingResource::TextureView(&placement_texture_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: bindings::shader::set0::S_DIFFUSE,
+                    resource: wgpu::BindingResource::Sampler(sampler),
+                },
+            ],
+            label: Some("chunk_bind_group"),
+        });
+
+        let bounds = Aabb::new(origin.as_f32(), size.as_f32());
+        let vertices = [
+            Vertex { position: vec2(bounds.min.x, bounds.min.y), tex_coords: vec2(0.0, 1.0) },
+            Vertex { position: vec2(bounds.max.x, bounds.min.y), tex_coords: vec2(1.0, 1.0) },
+            Vertex { position: vec2(bounds.max.x, bounds.max.y), tex_coords: vec2(1.0, 0.0) },
+            Vertex { position: vec2(bounds.min.x, bounds.max.y), tex_coords: vec2(0.0, 0.0) },
+        ];
+        let indices: [u16; 6] = [0, 1, 2, 0, 2, 3];
+
+        Ok(Chunk {
+            bounds,
+            quad: Mesh::new(&display.device, "Chunk Quad", &vertices, &indices),
+            bind_group
+        })
+    }
+
+    fn create_render_pipeline(display: &Display, texture_layout: &BindGroupLayout, camera_layout: &BindGroupLayout,
+                              time_layout: &BindGroupLayout, animation_layout: &BindGroupLayout, permutation: u32) -> Rc<wgpu::RenderPipeline> {
+        let vertex_shader = include_spirv_out!("shader.vert.spv");
+        let fragment_shader = shaders::shader_frag::descriptor(permutation);
+        display.pipeline(
+            &PipelineBuilder::new("Tilemap Pipeline", &vertex_shader, &fragment_shader, display.sc_desc.format)
+                .vertex::<Vertex>(wgpu::InputStepMode::Vertex)
+                .bind_group(texture_layout)
+                .bind_group(camera_layout)
+                .bind_group(time_layout)
+                .bind_group(animation_layout)
+                .blend(BlendMode::Alpha)
+                .cull_mode(wgpu::CullMode::Back)
+                .permutation(permutation)
+        )
+    }
+
+    /// Switches to another variant of `shader.frag`.
+    pub fn set_permutation(&mut self, display: &Display, camera_layout: &BindGroupLayout, permutation: u32) {
+        if permutation != self.permutation {
+            self.render_pipeline = Self::create_render_pipeline(display, &self.bind_group_layout, camera_layout, self.time_buffer.layout(), self.animation_buffer.layout(), permutation);
+            self.permutation = permutation;
+        }
+    }
+
+    /// Advances the tile animations.
+    pub fn update(&mut self, queue: &wgpu::Queue, dt: f32) {
+        self.time += dt as f64;
+        queue.update_uniform_buffer(&self.time_buffer, &((self.time * 1000.0) as u64 as u32));
+    }
+
+    pub fn chunk_count(&self) -> usize {
+        self.chunks.len()
+    }
+
+    fn visible_chunks<'a>(&'a self, camera: &Camera) -> impl Iterator<Item=&'a Chunk> {
+        let view = camera.bounds();
+        self.chunks.iter().filter(move |chunk| chunk.bounds.overlaps(&view))
+    }
+
+    pub fn visible_chunk_count(&self, camera: &Camera) -> usize {
+        self.visible_chunks(camera).count()
+    }
+
+    pub fn render<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>, camera: &Camera, camera_buffer: &'a UniformBuffer<Mat4>) {
+        render_pass.set_pipeline(&self.render_pipeline);
+        render_pass.set_uniform_buffer(1, camera_buffer);
+        render_pass.set_uniform_buffer(2, &self.time_buffer);
+        render_pass.set_storage_buffer(3, &self.animation_buffer);
+        for chunk in self.visible_chunks(camera) {
+            render_pass.set_bind_group(0, &chunk.bind_group, &[]);
+            chunk.quad.draw(render_pass);
+        }
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use crate::tilemap::TilesetProperties;
+    use super::*;
+
+    /// `textures/tilesheet.png` has 512x512 pixels with 64x64 pixel tiles.
+    const TILE_COUNT: u32 = 64;
+
+    fn animation(tile: u32, frames: &[u32]) -> TileAnimation {
+        TileAnimation { tile, frames: frames.to_vec(), frame_duration: 0.1 }
+    }
+
+    #[test]
+    fn lays_out_the_real_animations() {
+        let properties = TilesetProperties::load(&Path::new(env!("CARGO_MANIFEST_DIR")).join("assets/tiles.json")).unwrap();
+        let animations = &properties.get("default").unwrap().animations;
+        let table = animation_table(animations, TILE_COUNT).unwrap();
+
+        let entry_count = table[0].x as usize;
+        for animation in animations {
+            let entry = table[1 + animation.tile as usize];
+            let frames = &table[entry.x as usize..(entry.x + entry.y) as usize];
+            assert_eq!(frames.iter().map(|frame| frame.x).collect::<Vec<_>>(), animation.frames);
+            assert_eq!(entry.z, (animation.frame_duration * 1000.0).round() as u32);
+        }
+        assert_eq!(table.len(), 1 + entry_count + animations.iter().map(|animation| animation.frames.len()).sum::<usize>());
+    }
+
+    #[test]
+    fn only_has_entries_up_to_the_last_animated_tile() {
+        let table = animation_table(&[animation(2, &[2, 3])], TILE_COUNT).unwrap();
+        assert_eq!(table, vec![uvec4(3, 0, 0, 0), UVec4::ZERO, UVec4::ZERO, uvec4(4, 2, 100, 0), uvec4(2, 0, 0, 0), uvec4(3, 0, 0, 0)]);
+    }
+
+    #[test]
+    fn rejects_tiles_outside_of_the_tileset() {
+        assert!(animation_table(&[animation(TILE_COUNT, &[0])], TILE_COUNT).is_err());
+        assert!(animation_table(&[animation(0, &[1, TILE_COUNT])], TILE_COUNT).is_err());
+        assert!(animation_table(&[animation(0, &[])], TILE_COUNT).is_err());
+        assert!(animation_table(&[animation(TILE_COUNT - 1, &[TILE_COUNT - 1])], TILE_COUNT).is_ok());
+    }
+}
@@ -0,0 +1,64 @@
+use glam::*;
+use serde::Deserialize;
+use crate::catalog::Catalog;
+use crate::physics::Aabb;
+
+const BOB_HEIGHT: f32 = 0.1;
+const BOB_SPEED: f32 = 3.0;
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct CollectibleKind {
+    pub size: [f32; 2],
+    pub score: u32,
+    #[serde(default = "default_tile")]
+    pub tile: i32,
+    #[serde(default = "default_color")]
+    pub color: [f32; 4]
+}
+
+fn default_tile() -> i32 {
+    -1
+}
+
+fn default_color() -> [f32; 4] {
+    [1.0; 4]
+}
+
+pub type CollectibleKinds = Catalog<CollectibleKind>;
+
+#[derive(Clone)]
+pub struct Collectible {
+    pub kind: CollectibleKind,
+    pub position: Vec2,
+    pub collected: bool
+}
+
+impl Collectible {
+
+    /// Collectibles float centered half a tile above their spawn point.
+    pub fn new(kind: &CollectibleKind, spawn: Vec2) -> Self {
+        Self {
+            kind: kind.clone(),
+            position: spawn + vec2(-kind.size[0], 1.0 - kind.size[1]) * 0.5,
+            collected: false
+        }
+    }
+
+    pub fn aabb(&self) -> Aabb {
+        Aabb::new(self.position, Vec2::from(self.kind.size))
+    }
+
+    /// Marks the collectible as collected if `other` touches it and returns the score it is worth.
+    pub fn collect(&mut self, other: &Aabb) -> Option<u32> {
+        if self.collected || !self.aabb().overlaps(other) {
+            return None;
+        }
+        self.collected = true;
+        Some(self.kind.score)
+    }
+
+    pub fn draw_position(&self, time: f32) -> Vec2 {
+        self.position + vec2(0.0, BOB_HEIGHT * f32::sin(time * BOB_SPEED + self.position.x))
+    }
+
+}
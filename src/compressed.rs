@@ -0,0 +1,604 @@
+use std::path::Path;
+use std::convert::TryInto;
+use anyhow::*;
+use crate::texture::{TextureData, TextureKind, MipMaps};
+
+const DDS_MAGIC: &[u8; 4] = b"DDS ";
+const KTX2_IDENTIFIER: &[u8; 12] = &[0xAB, 0x4B, 0x54, 0x58, 0x20, 0x32, 0x30, 0xBB, 0x0D, 0x0A, 0x1A, 0x0A];
+
+/// Pre-built texture data as stored in a KTX2 or DDS container, usually block compressed.
+/// Supports 2d textures, arrays and cube maps with their full mip chain.
+/// Only BC1 to BC5 and RGBA8 can be decompressed on the cpu, so ETC2, ASTC, BC6H and BC7 textures only load
+/// on adapters that sample them natively.
+pub struct CompressedTextureData {
+    format: wgpu::TextureFormat,
+    width: u32,
+    height: u32,
+    /// Array layers times faces.
+    layers: u32,
+    cube: bool,
+    mipmaps: u32,
+    /// Layer after layer, every layer with its levels from largest to smallest like `TextureData`.
+    data: Vec<u8>
+}
+
+/// Bytes per block and block size in pixels.
+fn block_info(format: wgpu::TextureFormat) -> Option<(u32, u32)> {
+    use wgpu::TextureFormat::*;
+    Some(match format {
+        Rgba8Unorm | Rgba8UnormSrgb => (4, 1),
+        Bc1RgbaUnorm | Bc1RgbaUnormSrgb | Bc4RUnorm | Etc2RgbUnorm | Etc2RgbUnormSrgb | Etc2RgbA1Unorm | Etc2RgbA1UnormSrgb => (8, 4),
+        Bc2RgbaUnorm | Bc2RgbaUnormSrgb | Bc3RgbaUnorm | Bc3RgbaUnormSrgb | Bc5RgUnorm | Bc6hRgbUfloat | Bc6hRgbSfloat
+        | Bc7RgbaUnorm | Bc7RgbaUnormSrgb | Etc2RgbA8Unorm | Etc2RgbA8UnormSrgb | Astc4x4RgbaUnorm | Astc4x4RgbaUnormSrgb => (16, 4),
+        _ => return None
+    })
+}
+
+/// The device feature needed to sample `format`.
+fn required_features(format: wgpu::TextureFormat) -> wgpu::Features {
+    use wgpu::TextureFormat::*;
+    match format {
+        Bc1RgbaUnorm | Bc1RgbaUnormSrgb | Bc2RgbaUnorm | Bc2RgbaUnormSrgb | Bc3RgbaUnorm | Bc3RgbaUnormSrgb | Bc4RUnorm
+        | Bc5RgUnorm | Bc6hRgbUfloat | Bc6hRgbSfloat | Bc7RgbaUnorm | Bc7RgbaUnormSrgb => wgpu::Features::TEXTURE_COMPRESSION_BC,
+        Etc2RgbUnorm | Etc2RgbUnormSrgb | Etc2RgbA1Unorm | Etc2RgbA1UnormSrgb | Etc2RgbA8Unorm | Etc2RgbA8UnormSrgb => wgpu::Features::TEXTURE_COMPRESSION_ETC2,
+        Astc4x4RgbaUnorm | Astc4x4RgbaUnormSrgb => wgpu::Features::TEXTURE_COMPRESSION_ASTC_LDR,
+        _ => wgpu::Features::empty()
+    }
+}
+
+/// Whether `decompress` can decode `format` for adapters without the feature to sample it.
+fn has_cpu_decoder(format: wgpu::TextureFormat) -> bool {
+    use wgpu::TextureFormat::*;
+    matches!(format, Rgba8Unorm | Rgba8UnormSrgb | Bc1RgbaUnorm | Bc1RgbaUnormSrgb | Bc2RgbaUnorm | Bc2RgbaUnormSrgb
+        | Bc3RgbaUnorm | Bc3RgbaUnormSrgb | Bc4RUnorm | Bc5RgUnorm)
+}
+
+fn is_srgb(format: wgpu::TextureFormat) -> bool {
+    use wgpu::TextureFormat::*;
+    matches!(format, Rgba8UnormSrgb | Bc1RgbaUnormSrgb | Bc2RgbaUnormSrgb | Bc3RgbaUnormSrgb | Bc7RgbaUnormSrgb
+        | Etc2RgbUnormSrgb | Etc2RgbA1UnormSrgb | Etc2RgbA8UnormSrgb | Astc4x4RgbaUnormSrgb)
+}
+
+fn dxgi_format(id: u32) -> Option<wgpu::TextureFormat> {
+    use wgpu::TextureFormat::*;
+    Some(match id {
+        28 => Rgba8Unorm,
+        29 => Rgba8UnormSrgb,
+        71 => Bc1RgbaUnorm,
+        72 => Bc1RgbaUnormSrgb,
+        74 => Bc2RgbaUnorm,
+        75 => Bc2RgbaUnormSrgb,
+        77 => Bc3RgbaUnorm,
+        78 => Bc3RgbaUnormSrgb,
+        80 => Bc4RUnorm,
+        83 => Bc5RgUnorm,
+        95 => Bc6hRgbUfloat,
+        96 => Bc6hRgbSfloat,
+        98 => Bc7RgbaUnorm,
+        99 => Bc7RgbaUnormSrgb,
+        _ => return None
+    })
+}
+
+fn vk_format(id: u32) -> Option<wgpu::TextureFormat> {
+    use wgpu::TextureFormat::*;
+    Some(match id {
+        37 => Rgba8Unorm,
+        43 => Rgba8UnormSrgb,
+        131 | 133 => Bc1RgbaUnorm,
+        132 | 134 => Bc1RgbaUnormSrgb,
+        135 => Bc2RgbaUnorm,
+        136 => Bc2RgbaUnormSrgb,
+        137 => Bc3RgbaUnorm,
+        138 => Bc3RgbaUnormSrgb,
+        139 => Bc4RUnorm,
+        141 => Bc5RgUnorm,
+        143 => Bc6hRgbUfloat,
+        144 => Bc6hRgbSfloat,
+        145 => Bc7RgbaUnorm,
+        146 => Bc7RgbaUnormSrgb,
+        147 => Etc2RgbUnorm,
+        148 => Etc2RgbUnormSrgb,
+        149 => Etc2RgbA1Unorm,
+        150 => Etc2RgbA1UnormSrgb,
+        151 => Etc2RgbA8Unorm,
+        152 => Etc2RgbA8UnormSrgb,
+        157 => Astc4x4RgbaUnorm,
+        158 => Astc4x4RgbaUnormSrgb,
+        _ => return None
+    })
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> Result<u32, Error> {
+    let bytes = bytes.get(offset..offset + 4).context("Unexpected end of file")?;
+    Ok(u32::from_le_bytes(bytes.try_into().unwrap()))
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> Result<u64, Error> {
+    let bytes = bytes.get(offset..offset + 8).context("Unexpected end of file")?;
+    Ok(u64::from_le_bytes(bytes.try_into().unwrap()))
+}
+
+fn mipmaped_size(size: u32, level: u32) -> u32 {
+    (size >> level).max(1)
+}
+
+impl CompressedTextureData {
+
+    /// Picks the container by the file extension. Fails for formats that the adapter with `features`
+    /// can't sample and that have no cpu decoder either.
+    pub fn load(path: &Path, features: wgpu::Features) -> Result<Self, Error> {
+        let bytes = std::fs::read(path).with_context(|| format!("Can't read {}", path.display()))?;
+        let texture = match path.extension().and_then(|ext| ext.to_str()) {
+            Some("ktx2") => Self::from_ktx2(&bytes),
+            Some("dds") => Self::from_dds(&bytes),
+            _ => bail!("Unsupported texture container: {}", path.display())
+        }.with_context(|| format!("Can't load {}", path.display()))?;
+        ensure!(texture.is_supported(features) || has_cpu_decoder(texture.format),
+            "{} is {:?}, which the adapter can't sample and which has no cpu decoder. Only BC1 to BC5 and RGBA8 are decompressed on the cpu",
+            path.display(), texture.format);
+        Ok(texture)
+    }
+
+    pub fn from_dds(bytes: &[u8]) -> Result<Self, Error> {
+        ensure!(bytes.starts_with(DDS_MAGIC), "Not a dds file");
+        let height = read_u32(bytes, 12)?;
+        let width = read_u32(bytes, 16)?;
+        let depth = read_u32(bytes, 24)?;
+        let mipmaps = read_u32(bytes, 28)?.max(1);
+        let four_cc = bytes.get(84..88).context("Unexpected end of file")?;
+        let caps2 = read_u32(bytes, 112)?;
+        ensure!(depth <= 1, "3d dds textures are not supported");
+
+        let (format, layers, cube, offset) = if four_cc == b"DX10" {
+            let format = dxgi_format(read_u32(bytes, 128)?).context("Unsupported dxgi format")?;
+            let cube = read_u32(bytes, 136)? & 0x4 != 0;
+            let layers = read_u32(bytes, 140)?.max(1);
+            let layers = if cube { layers.checked_mul(6).context("Too many dds layers")? } else { layers };
+            (format, layers, cube, 148)
+        } else {
+            let format = match four_cc {
+                b"DXT1" => wgpu::TextureFormat::Bc1RgbaUnorm,
+                b"DXT3" => wgpu::TextureFormat::Bc2RgbaUnorm,
+                b"DXT5" => wgpu::TextureFormat::Bc3RgbaUnorm,
+                b"ATI1" | b"BC4U" => wgpu::TextureFormat::Bc4RUnorm,
+                b"ATI2" | b"BC5U" => wgpu::TextureFormat::Bc5RgUnorm,
+                _ => bail!("Unsupported dds pixel format {:?}", String::from_utf8_lossy(four_cc))
+            };
+            let cube = caps2 & 0x200 != 0;
+            (format, if cube { 6 } else { 1 }, cube, 128)
+        };
+
+        let mut texture = Self { format, width, height, layers, cube, mipmaps, data: Vec::new() };
+        let size = texture.data_size()?;
+        texture.data = offset.checked_add(size)
+            .and_then(|end| bytes.get(offset..end))
+            .context("The dds file is truncated")?
+            .to_vec();
+        Ok(texture)
+    }
+
+    pub fn from_ktx2(bytes: &[u8]) -> Result<Self, Error> {
+        ensure!(bytes.starts_with(KTX2_IDENTIFIER), "Not a ktx2 file");
+        let format = vk_format(read_u32(bytes, 12)?).context("Unsupported vulkan format")?;
+        let width = read_u32(bytes, 20)?;
+        let height = read_u32(bytes, 24)?.max(1);
+        ensure!(read_u32(bytes, 28)? <= 1, "3d ktx2 textures are not supported");
+        let array_layers = read_u32(bytes, 32)?.max(1);
+        let faces = read_u32(bytes, 36)?;
+        ensure!(faces == 1 || faces == 6, "A ktx2 texture has 1 or 6 faces, not {}", faces);
+        let mipmaps = read_u32(bytes, 40)?.max(1);
+        ensure!(read_u32(bytes, 44)? == 0, "Supercompressed ktx2 files are not supported");
+
+        let layers = array_layers.checked_mul(faces).context("Too many ktx2 layers")?;
+        let mut texture = Self { format, width, height, layers, cube: faces == 6, mipmaps, data: Vec::new() };
+        let data_size = texture.data_size()?;
+
+        // Ktx2 stores every level with all its layers, this reorders them into layers with all their levels
+        let levels = (0..mipmaps).map(|level| {
+            let offset = read_u64(bytes, 80 + level as usize * 24)? as usize;
+            let size = texture.level_size(level) * layers as usize;
+            offset.checked_add(size)
+                .and_then(|end| bytes.get(offset..end))
+                .context("The ktx2 file is truncated")
+        }).collect::<Result<Vec<_>, Error>>()?;
+        texture.data.reserve(data_size);
+        for layer in 0..layers as usize {
+            for (level, data) in levels.iter().enumerate() {
+                let size = texture.level_size(level as u32);
+                texture.data.extend_from_slice(&data[layer * size..(layer + 1) * size]);
+            }
+        }
+        Ok(texture)
+    }
+
+    /// Array layers times faces.
+    pub fn layers(&self) -> u32 {
+        self.layers
+    }
+
+    /// Whether the device can sample the texture without decompressing it first.
+    pub fn is_supported(&self, features: wgpu::Features) -> bool {
+        features.contains(required_features(self.format))
+    }
+
+    fn blocks(&self, level: u32) -> (u32, u32) {
+        let (_, block) = block_info(self.format).unwrap();
+        let blocks = |size: u32| (mipmaped_size(size, level) - 1) / block + 1;
+        (blocks(self.width), blocks(self.height))
+    }
+
+    fn level_size(&self, level: u32) -> usize {
+        let (bytes, _) = block_info(self.format).unwrap();
+        let (x, y) = self.blocks(level);
+        x as usize * y as usize * bytes as usize
+    }
+
+    fn layer_size(&self) -> usize {
+        (0..self.mipmaps).map(|level| self.level_size(level)).sum()
+    }
+
+    /// Checks the header values and returns the size of all layers with their levels.
+    fn data_size(&self) -> Result<usize, Error> {
+        ensure!(self.width > 0 && self.height > 0, "The texture is {}x{} pixels", self.width, self.height);
+        ensure!(self.layers > 0, "The texture has no layers");
+        let max_levels = 32 - self.width.max(self.height).leading_zeros();
+        ensure!(self.mipmaps <= max_levels, "A {}x{} texture can't have {} mip levels", self.width, self.height, self.mipmaps);
+        let (bytes, _) = block_info(self.format).unwrap();
+        (0..self.mipmaps)
+            .try_fold(0usize, |sum, level| {
+                let (x, y) = self.blocks(level);
+                (x as usize).checked_mul(y as usize)?.checked_mul(bytes as usize)?.checked_add(sum)
+            })
+            .and_then(|size| size.checked_mul(self.layers as usize))
+            .context("The texture is too large")
+    }
+
+    fn level_data(&self, layer: u32, level: u32) -> &[u8] {
+        let start = layer as usize * self.layer_size() + (0..level).map(|l| self.level_size(l)).sum::<usize>();
+        &self.data[start..start + self.level_size(level)]
+    }
+
+    fn kind(&self) -> TextureKind {
+        match (self.cube, self.layers) {
+            (true, 6) => TextureKind::Cube,
+            (_, 1) => TextureKind::D2,
+            _ => TextureKind::D2Array
+        }
+    }
+
+    pub fn view_dimension(&self) -> wgpu::TextureViewDimension {
+        match self.kind() {
+            TextureKind::Cube => wgpu::TextureViewDimension::Cube,
+            TextureKind::D2 => wgpu::TextureViewDimension::D2,
+            _ => wgpu::TextureViewDimension::D2Array
+        }
+    }
+
+    /// Uploads the blocks as they are if `features` allow it, otherwise decompresses them into RGBA8 first.
+    pub fn to_texture(&self, device: &wgpu::Device, queue: &wgpu::Queue, features: wgpu::Features, usage: wgpu::TextureUsage) -> Result<wgpu::Texture, Error> {
+        if !self.is_supported(features) {
+            log::info!("Decompressing {:?} texture on the cpu", self.format);
+            let format = if is_srgb(self.format) { wgpu::TextureFormat::Rgba8UnormSrgb } else { wgpu::TextureFormat::Rgba8Unorm };
+            return self.decompress()?.to_texture(device, queue, format, usage);
+        }
+
+        let (block_bytes, block) = block_info(self.format).unwrap();
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            size: wgpu::Extent3d {
+                width: self.width,
+                height: self.height,
+                depth: self.layers
+            },
+            mip_level_count: self.mipmaps,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: self.format,
+            usage: usage | wgpu::TextureUsage::COPY_DST,
+            label: Some("compressed_texture"),
+        });
+        for layer in 0..self.layers {
+            for level in 0..self.mipmaps {
+                let (blocks_x, blocks_y) = self.blocks(level);
+                queue.write_texture(
+                    wgpu::TextureCopyView {
+                        texture: &texture,
+                        mip_level: level,
+                        origin: wgpu::Origin3d { x: 0, y: 0, z: layer }
+                    },
+                    self.level_data(layer, level),
+                    wgpu::TextureDataLayout {
+                        offset: 0,
+                        bytes_per_row: blocks_x * block_bytes,
+                        rows_per_image: blocks_y * block
+                    },
+                    // Copies of compressed textures cover whole blocks
+                    wgpu::Extent3d {
+                        width: blocks_x * block,
+                        height: blocks_y * block,
+                        depth: 1
+                    });
+            }
+        }
+        Ok(texture)
+    }
+
+    /// Decodes BC1 to BC5 and uncompressed RGBA8. ETC2, ASTC, BC6H and BC7 have no cpu decoder.
+    pub fn decompress(&self) -> Result<TextureData<[u8; 4]>, Error> {
+        use wgpu::TextureFormat::*;
+        ensure!(has_cpu_decoder(self.format), "No cpu decoder for {:?}", self.format);
+        let mut result = TextureData::<[u8; 4]>::with_kind(self.kind(), self.width, self.height, self.layers, MipMaps::Some(self.mipmaps));
+        for layer in 0..self.layers {
+            for level in 0..self.mipmaps {
+                let data = self.level_data(layer, level);
+                let width = mipmaped_size(self.width, level);
+                let height = mipmaped_size(self.height, level);
+                let target = result.get_mipmap_mut(layer, level);
+                if let Rgba8Unorm | Rgba8UnormSrgb = self.format {
+                    target.copy_from_slice(bytemuck::cast_slice(data));
+                    continue;
+                }
+                let (block_bytes, _) = block_info(self.format).unwrap();
+                let (blocks_x, _) = self.blocks(level);
+                for (i, block) in data.chunks(block_bytes as usize).enumerate() {
+                    let pixels = match self.format {
+                        Bc1RgbaUnorm | Bc1RgbaUnormSrgb => decode_bc1(block, true),
+                        Bc2RgbaUnorm | Bc2RgbaUnormSrgb => decode_bc2(block),
+                        Bc3RgbaUnorm | Bc3RgbaUnormSrgb => decode_bc3(block),
+                        Bc4RUnorm => decode_bc4(block),
+                        Bc5RgUnorm => decode_bc5(block),
+                        _ => unreachable!()
+                    };
+                    let (bx, by) = (i as u32 % blocks_x * 4, i as u32 / blocks_x * 4);
+                    for (j, pixel) in pixels.iter().enumerate() {
+                        let (x, y) = (bx + j as u32 % 4, by + j as u32 / 4);
+                        if x < width && y < height {
+                            target[(x + y * width) as usize] = *pixel;
+                        }
+                    }
+                }
+            }
+        }
+        Ok(result)
+    }
+
+}
+
+fn rgb565(value: u16) -> [u8; 3] {
+    let r = (value >> 11) & 0x1f;
+    let g = (value >> 5) & 0x3f;
+    let b = value & 0x1f;
+    [((r << 3) | (r >> 2)) as u8, ((g << 2) | (g >> 4)) as u8, ((b << 3) | (b >> 2)) as u8]
+}
+
+fn mix(a: [u8; 3], b: [u8; 3], wa: u32, wb: u32) -> [u8; 3] {
+    let channel = |i: usize| ((a[i] as u32 * wa + b[i] as u32 * wb) / (wa + wb)) as u8;
+    [channel(0), channel(1), channel(2)]
+}
+
+/// `punch_through` enables the transparent black of BC1 blocks whose first endpoint is not the larger one.
+fn decode_bc1(block: &[u8], punch_through: bool) -> [[u8; 4]; 16] {
+    let c0 = u16::from_le_bytes([block[0], block[1]]);
+    let c1 = u16::from_le_bytes([block[2], block[3]]);
+    let (a, b) = (rgb565(c0), rgb565(c1));
+    let palette = if c0 > c1 || !punch_through {
+        [a, b, mix(a, b, 2, 1), mix(a, b, 1, 2)]
+    } else {
+        [a, b, mix(a, b, 1, 1), [0, 0, 0]]
+    };
+    let indices = u32::from_le_bytes([block[4], block[5], block[6], block[7]]);
+    let mut pixels = [[0u8; 4]; 16];
+    for (i, pixel) in pixels.iter_mut().enumerate() {
+        let index = ((indices >> (2 * i)) & 0x3) as usize;
+        let alpha = if index == 3 && c0 <= c1 && punch_through { 0 } else { 255 };
+        let color = palette[index];
+        *pixel = [color[0], color[1], color[2], alpha];
+    }
+    pixels
+}
+
+fn decode_bc2(block: &[u8]) -> [[u8; 4]; 16] {
+    let mut pixels = decode_bc1(&block[8..], false);
+    let alpha = u64::from_le_bytes(block[0..8].try_into().unwrap());
+    for (i, pixel) in pixels.iter_mut().enumerate() {
+        pixel[3] = ((alpha >> (4 * i)) & 0xf) as u8 * 17;
+    }
+    pixels
+}
+
+/// The interpolated single channel blocks of BC3 alpha, BC4 and BC5.
+fn decode_channel(block: &[u8]) -> [u8; 16] {
+    let (a, b) = (block[0] as u32, block[1] as u32);
+    let palette: Vec<u8> = if a > b {
+        (0..8).map(|i| match i {
+            0 => a,
+            1 => b,
+            _ => ((8 - i) * a + (i - 1) * b) / 7
+        } as u8).collect()
+    } else {
+        (0..8).map(|i| match i {
+            0 => a,
+            1 => b,
+            6 => 0,
+            7 => 255,
+            _ => ((6 - i) * a + (i - 1) * b) / 5
+        } as u8).collect()
+    };
+    let mut bits = [0u8; 8];
+    bits[..6].copy_from_slice(&block[2..8]);
+    let indices = u64::from_le_bytes(bits);
+    let mut values = [0u8; 16];
+    for (i, value) in values.iter_mut().enumerate() {
+        *value = palette[((indices >> (3 * i)) & 0x7) as usize];
+    }
+    values
+}
+
+fn decode_bc3(block: &[u8]) -> [[u8; 4]; 16] {
+    let mut pixels = decode_bc1(&block[8..], false);
+    for (pixel, alpha) in pixels.iter_mut().zip(decode_channel(&block[..8]).iter()) {
+        pixel[3] = *alpha;
+    }
+    pixels
+}
+
+fn decode_bc4(block: &[u8]) -> [[u8; 4]; 16] {
+    let mut pixels = [[0, 0, 0, 255]; 16];
+    for (pixel, red) in pixels.iter_mut().zip(decode_channel(block).iter()) {
+        pixel[0] = *red;
+    }
+    pixels
+}
+
+fn decode_bc5(block: &[u8]) -> [[u8; 4]; 16] {
+    let mut pixels = decode_bc4(&block[..8]);
+    for (pixel, green) in pixels.iter_mut().zip(decode_channel(&block[8..]).iter()) {
+        pixel[1] = *green;
+    }
+    pixels
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RED: u16 = 0xf800;
+    const BLUE: u16 = 0x001f;
+
+    fn bc1_block(c0: u16, c1: u16, indices: [u8; 16]) -> Vec<u8> {
+        let bits = indices.iter().enumerate().fold(0u32, |bits, (i, &index)| bits | (index as u32) << (2 * i));
+        [c0.to_le_bytes(), c1.to_le_bytes()].concat().into_iter().chain(bits.to_le_bytes().iter().copied()).collect()
+    }
+
+    fn channel_block(a: u8, b: u8, indices: [u8; 16]) -> Vec<u8> {
+        let bits = indices.iter().enumerate().fold(0u64, |bits, (i, &index)| bits | (index as u64) << (3 * i));
+        std::iter::once(a).chain(std::iter::once(b)).chain(bits.to_le_bytes()[..6].iter().copied()).collect()
+    }
+
+    fn put(bytes: &mut [u8], offset: usize, value: u32) {
+        bytes[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+    }
+
+    /// A DX10 dds header of RGBA8 layers.
+    fn dds(width: u32, height: u32, mipmaps: u32, layers: u32) -> Vec<u8> {
+        let mut bytes = vec![0; 148];
+        bytes[..4].copy_from_slice(DDS_MAGIC);
+        put(&mut bytes, 12, height);
+        put(&mut bytes, 16, width);
+        put(&mut bytes, 28, mipmaps);
+        bytes[84..88].copy_from_slice(b"DX10");
+        put(&mut bytes, 128, 28);
+        put(&mut bytes, 140, layers);
+        bytes
+    }
+
+    /// A ktx2 header of RGBA8 layers with an empty level index.
+    fn ktx2(width: u32, height: u32, mipmaps: u32, layers: u32, faces: u32) -> Vec<u8> {
+        let mut bytes = vec![0; 80 + 24 * mipmaps as usize];
+        bytes[..12].copy_from_slice(KTX2_IDENTIFIER);
+        put(&mut bytes, 12, 37);
+        put(&mut bytes, 20, width);
+        put(&mut bytes, 24, height);
+        put(&mut bytes, 32, layers);
+        put(&mut bytes, 36, faces);
+        put(&mut bytes, 40, mipmaps);
+        bytes
+    }
+
+    #[test]
+    fn decodes_bc1_with_four_colors() {
+        let pixels = decode_bc1(&bc1_block(RED, BLUE, [0, 1, 2, 3, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]), true);
+        assert_eq!(&pixels[..4], &[[255, 0, 0, 255], [0, 0, 255, 255], [170, 0, 85, 255], [85, 0, 170, 255]]);
+        assert_eq!(pixels[15], [255, 0, 0, 255]);
+    }
+
+    #[test]
+    fn decodes_bc1_with_punch_through_alpha() {
+        let block = bc1_block(BLUE, RED, [0, 1, 2, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3]);
+        let pixels = decode_bc1(&block, true);
+        assert_eq!(&pixels[..4], &[[0, 0, 255, 255], [255, 0, 0, 255], [127, 0, 127, 255], [0, 0, 0, 0]]);
+        // The color blocks of BC2 and BC3 always have four colors
+        let pixels = decode_bc1(&block, false);
+        assert_eq!(&pixels[2..4], &[[85, 0, 170, 255], [170, 0, 85, 255]]);
+    }
+
+    #[test]
+    fn decodes_bc3_alpha_with_eight_values() {
+        let block = [channel_block(255, 0, [0, 1, 2, 3, 4, 5, 6, 7, 0, 0, 0, 0, 0, 0, 0, 0]), bc1_block(0xffff, 0xffff, [0; 16])].concat();
+        let pixels = decode_bc3(&block);
+        let alpha = pixels.iter().map(|pixel| pixel[3]).collect::<Vec<_>>();
+        assert_eq!(&alpha[..8], &[255, 0, 218, 182, 145, 109, 72, 36]);
+        assert!(pixels.iter().all(|pixel| pixel[..3] == [255, 255, 255]));
+    }
+
+    #[test]
+    fn decodes_bc3_alpha_with_six_values() {
+        let block = [channel_block(0, 255, [0, 1, 2, 3, 4, 5, 6, 7, 0, 0, 0, 0, 0, 0, 0, 0]), bc1_block(0, 0, [0; 16])].concat();
+        let alpha = decode_bc3(&block).iter().map(|pixel| pixel[3]).collect::<Vec<_>>();
+        assert_eq!(&alpha[..8], &[0, 255, 51, 102, 153, 204, 0, 255]);
+    }
+
+    #[test]
+    fn decodes_bc5() {
+        let block = [channel_block(200, 100, [0; 16]), channel_block(10, 20, [1; 16])].concat();
+        assert_eq!(decode_bc5(&block), [[200, 20, 0, 255]; 16]);
+    }
+
+    #[test]
+    fn reads_dds_layers() {
+        let mut bytes = dds(2, 2, 2, 2);
+        for (value, size) in [(0, 16), (1, 4), (2, 16), (3, 4)].iter() {
+            bytes.extend(std::iter::repeat(*value).take(*size));
+        }
+        let texture = CompressedTextureData::from_dds(&bytes).unwrap();
+        assert_eq!(texture.layers(), 2);
+        assert_eq!(texture.view_dimension(), wgpu::TextureViewDimension::D2Array);
+        assert_eq!(texture.level_data(1, 0), &[2; 16]);
+        assert_eq!(texture.level_data(0, 1), &[1; 4]);
+        let decompressed = texture.decompress().unwrap();
+        assert_eq!(decompressed.get_mipmap(1, 1), &[[3, 3, 3, 3]]);
+    }
+
+    #[test]
+    fn reads_dds_cube_maps() {
+        let mut bytes = dds(4, 4, 1, 1);
+        bytes.truncate(128);
+        bytes[84..88].copy_from_slice(b"DXT1");
+        put(&mut bytes, 112, 0x200);
+        bytes.extend((0..6).flat_map(|_| bc1_block(RED, BLUE, [0; 16])));
+        let texture = CompressedTextureData::from_dds(&bytes).unwrap();
+        assert_eq!(texture.layers(), 6);
+        assert_eq!(texture.view_dimension(), wgpu::TextureViewDimension::Cube);
+        assert!(CompressedTextureData::from_dds(&bytes[..bytes.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn reorders_ktx2_levels_into_layers() {
+        let mut bytes = ktx2(2, 2, 2, 2, 1);
+        // The smallest level comes first in the file
+        let (level1, level0) = (bytes.len(), bytes.len() + 8);
+        put(&mut bytes, 80, level0 as u32);
+        put(&mut bytes, 88, 32);
+        put(&mut bytes, 104, level1 as u32);
+        put(&mut bytes, 112, 8);
+        for (value, size) in [(1, 4), (3, 4), (0, 16), (2, 16)].iter() {
+            bytes.extend(std::iter::repeat(*value).take(*size));
+        }
+        let texture = CompressedTextureData::from_ktx2(&bytes).unwrap();
+        let expected = [(0, 16), (1, 4), (2, 16), (3, 4)].iter()
+            .flat_map(|&(value, size)| std::iter::repeat(value).take(size))
+            .collect::<Vec<u8>>();
+        assert_eq!(texture.data, expected);
+        assert_eq!(texture.level_data(1, 1), &[3; 4]);
+    }
+
+    #[test]
+    fn rejects_invalid_headers() {
+        assert!(CompressedTextureData::from_ktx2(&ktx2(4, 4, 1, 1, 0)).is_err());
+        assert!(CompressedTextureData::from_ktx2(&ktx2(0, 4, 1, 1, 1)).is_err());
+        assert!(CompressedTextureData::from_ktx2(&ktx2(4, 4, 4, 1, 1)).is_err());
+        assert!(CompressedTextureData::from_dds(&dds(0, 4, 1, 1)).is_err());
+        assert!(CompressedTextureData::from_dds(&dds(u32::MAX, u32::MAX, 1, u32::MAX)).is_err());
+    }
+
+}
@@ -0,0 +1,398 @@
+use std::convert::TryInto;
+use std::fs::{create_dir_all, read, write};
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use anyhow::*;
+use glam::*;
+use ogmo3::Level;
+use crate::texture::{TextureData, TextureKind, TileLayout, MipMaps};
+use crate::mipmap::MipFilter;
+use crate::level::{LevelData, EntitySpawn};
+use crate::tilemap::TileGrid;
+use crate::autotile::AutotileRules;
+
+/// Has to be increased whenever the cooked formats or the way they are produced change, including the code of
+/// the mip filters.
+const COOK_VERSION: u32 = 3;
+const TILESET_MAGIC: &[u8; 4] = b"JRTS";
+const LEVEL_MAGIC: &[u8; 4] = b"JRLV";
+const EMPTY_TILE: u32 = u32::MAX;
+pub const TILESET_FILTER: MipFilter = MipFilter::Kaiser;
+
+/// Cooked assets live next to the raw ones in `assets/cooked`.
+fn cooked_path(base_path: &Path, source: &Path, extension: &str) -> PathBuf {
+    let name = source.file_stem().unwrap_or_default();
+    base_path.join("cooked").join(name).with_extension(extension)
+}
+
+/// FNV-1a over the cook version, the contents of every source and `settings`. Missing sources are optional
+/// and only hash their absence. A cooked asset whose stored fingerprint differs is stale.
+fn fingerprint(sources: &[&Path], settings: &[u32]) -> Result<u64, Error> {
+    let mut hash = 0xcbf29ce484222325u64;
+    let mut feed = |bytes: &[u8]| for byte in bytes {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    };
+    feed(&COOK_VERSION.to_le_bytes());
+    for source in sources {
+        match read(source) {
+            Ok(bytes) => {
+                feed(&[1]);
+                feed(&bytes);
+            }
+            Err(err) if err.kind() == ErrorKind::NotFound => feed(&[0]),
+            Err(err) => return Err(err).with_context(|| format!("Can't read {}", source.display()))
+        }
+    }
+    for setting in settings {
+        feed(&setting.to_le_bytes());
+    }
+    Ok(hash)
+}
+
+/// The tile layout and the mip filter the tileset is cooked with.
+fn tileset_settings(layout: &TileLayout) -> [u32; 8] {
+    [layout.tile_width, layout.tile_height, layout.margin_x, layout.margin_y, layout.spacing_x, layout.spacing_y, layout.extrusion,
+        TILESET_FILTER as u32]
+}
+
+#[derive(Default)]
+struct Writer {
+    bytes: Vec<u8>
+}
+
+impl Writer {
+
+    fn new(magic: &[u8; 4], fingerprint: u64) -> Self {
+        let mut writer = Self::default();
+        writer.bytes.extend_from_slice(magic);
+        writer.u64(fingerprint);
+        writer
+    }
+
+    fn u32(&mut self, value: u32) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn u64(&mut self, value: u64) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn vec2(&mut self, value: Vec2) {
+        self.bytes.extend_from_slice(&value.x.to_le_bytes());
+        self.bytes.extend_from_slice(&value.y.to_le_bytes());
+    }
+
+    fn bytes(&mut self, bytes: &[u8]) {
+        self.u32(bytes.len() as u32);
+        self.bytes.extend_from_slice(bytes);
+    }
+
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8]
+}
+
+impl<'a> Reader<'a> {
+
+    /// Returns `None` if the file is not of the expected kind or was cooked from different sources.
+    fn open(bytes: &'a [u8], magic: &[u8; 4], fingerprint: u64) -> Result<Option<Self>, Error> {
+        let mut reader = Self { bytes };
+        if reader.take(4)? != magic || reader.u64()? != fingerprint {
+            return Ok(None);
+        }
+        Ok(Some(reader))
+    }
+
+    fn take(&mut self, count: usize) -> Result<&'a [u8], Error> {
+        ensure!(self.bytes.len() >= count, "Unexpected end of cooked file");
+        let (head, tail) = self.bytes.split_at(count);
+        self.bytes = tail;
+        Ok(head)
+    }
+
+    fn u32(&mut self) -> Result<u32, Error> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Result<u64, Error> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    fn f32(&mut self) -> Result<f32, Error> {
+        Ok(f32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn vec2(&mut self) -> Result<Vec2, Error> {
+        Ok(vec2(self.f32()?, self.f32()?))
+    }
+
+    fn bytes(&mut self) -> Result<&'a [u8], Error> {
+        let len = self.u32()? as usize;
+        self.take(len)
+    }
+
+}
+
+fn write_cooked(path: &Path, writer: Writer) -> Result<(), Error> {
+    if let Some(dir) = path.parent() {
+        create_dir_all(dir)?;
+    }
+    write(path, writer.bytes).with_context(|| format!("Can't write {}", path.display()))?;
+    log::info!("Cooked {}", path.display());
+    Ok(())
+}
+
+/// Slices the tileset image at `source` and stores the layers with their mip chains.
+pub fn cook_tileset(base_path: &Path, source: &Path, layout: &TileLayout) -> Result<(), Error> {
+    let td = TextureData::parse_tileset(&source.to_path_buf(), layout, Some(TILESET_FILTER))?;
+    let mut writer = Writer::new(TILESET_MAGIC, fingerprint(&[source], &tileset_settings(layout))?);
+    writer.u32(td.width());
+    writer.u32(td.height());
+    writer.u32(td.depth());
+    writer.u32(td.mipmaps());
+    writer.u32(td.depth_x().context("The tileset has no layers per row")?);
+    writer.bytes(td.as_bytes());
+    write_cooked(&cooked_path(base_path, source, "tiles"), writer)
+}
+
+/// The cooked tileset, or `None` if it is missing or stale.
+pub fn load_cooked_tileset(base_path: &Path, source: &Path, layout: &TileLayout) -> Result<Option<TextureData<[u8; 4]>>, Error> {
+    let path = cooked_path(base_path, source, "tiles");
+    if !path.exists() {
+        return Ok(None);
+    }
+    let bytes = read(&path)?;
+    let mut reader = match Reader::open(&bytes, TILESET_MAGIC, fingerprint(&[source], &tileset_settings(layout))?)? {
+        Some(reader) => reader,
+        None => {
+            log::warn!("{} is stale, loading {} instead", path.display(), source.display());
+            return Ok(None);
+        }
+    };
+    let (width, height, depth, mipmaps) = (reader.u32()?, reader.u32()?, reader.u32()?, reader.u32()?);
+    let depth_divisor = reader.u32()?;
+    let mut td = TextureData::<[u8; 4]>::with_kind(TextureKind::D2Array, width, height, depth, MipMaps::Some(mipmaps));
+    let pixels = reader.bytes()?;
+    ensure!(pixels.len() == td.as_bytes().len(), "{} has the wrong size", path.display());
+    td.as_bytes_mut().copy_from_slice(pixels);
+    td.set_depth_divisor(Some(depth_divisor));
+    Ok(Some(td))
+}
+
+fn level_sources(base_path: &Path, source: &Path) -> [PathBuf; 3] {
+    // The tile ids depend on the tileset layout in the project and the autotile rules
+    [source.to_path_buf(), base_path.join("project.ogmo"), base_path.join("autotile.json")]
+}
+
+pub fn cook_level(base_path: &Path, source: &Path, tiles_per_row: u32, autotile: &AutotileRules) -> Result<(), Error> {
+    let level = LevelData::load(&Level::from_file(source)?, tiles_per_row, autotile)?;
+    let sources = level_sources(base_path, source);
+    let mut writer = Writer::new(LEVEL_MAGIC, fingerprint(&[&sources[0], &sources[1], &sources[2]], &[tiles_per_row])?);
+    let grid = &level.grid;
+    writer.u32(grid.width());
+    writer.u32(grid.height());
+    for y in 0..grid.height() as i32 {
+        for x in 0..grid.width() as i32 {
+            writer.u32(grid.get(x, y).unwrap_or(EMPTY_TILE));
+        }
+    }
+    let transforms = (0..grid.height() as i32)
+        .flat_map(|y| (0..grid.width() as i32).map(move |x| (x, y)))
+        .map(|(x, y)| grid.transform(x, y))
+        .collect::<Vec<_>>();
+    writer.bytes(&transforms);
+    writer.u32(level.spawns.len() as u32);
+    for spawn in &level.spawns {
+        writer.bytes(spawn.name.as_bytes());
+        writer.vec2(spawn.position);
+        writer.u32(spawn.nodes.len() as u32);
+        for node in &spawn.nodes {
+            writer.vec2(*node);
+        }
+    }
+    write_cooked(&cooked_path(base_path, source, "level"), writer)
+}
+
+fn read_level(reader: &mut Reader) -> Result<LevelData, Error> {
+    let mut grid = TileGrid::new(reader.u32()?, reader.u32()?);
+    for y in 0..grid.height() as i32 {
+        for x in 0..grid.width() as i32 {
+            let tile = reader.u32()?;
+            grid.set(x, y, Some(tile).filter(|&tile| tile != EMPTY_TILE));
+        }
+    }
+    let transforms = reader.bytes()?;
+    ensure!(transforms.len() == (grid.width() * grid.height()) as usize, "The tile transforms don't match the level size");
+    for (i, &transform) in transforms.iter().enumerate() {
+        grid.set_transform(i as i32 % grid.width() as i32, i as i32 / grid.width() as i32, transform);
+    }
+    let spawns = (0..reader.u32()?).map(|_| {
+        let name = String::from_utf8(reader.bytes()?.to_vec())?;
+        let position = reader.vec2()?;
+        let nodes = (0..reader.u32()?).map(|_| reader.vec2()).collect::<Result<_, Error>>()?;
+        Ok(EntitySpawn { name, position, nodes })
+    }).collect::<Result<_, Error>>()?;
+    Ok(LevelData { grid, spawns })
+}
+
+/// The cooked level, or `None` if it is missing or stale.
+fn load_cooked_level(base_path: &Path, source: &Path, tiles_per_row: u32) -> Result<Option<LevelData>, Error> {
+    let path = cooked_path(base_path, source, "level");
+    if !path.exists() {
+        return Ok(None);
+    }
+    let sources = level_sources(base_path, source);
+    let bytes = read(&path)?;
+    match Reader::open(&bytes, LEVEL_MAGIC, fingerprint(&[&sources[0], &sources[1], &sources[2]], &[tiles_per_row])?)? {
+        Some(mut reader) => Ok(Some(read_level(&mut reader).with_context(|| format!("Can't load {}", path.display()))?)),
+        None => {
+            log::warn!("{} is stale, loading {} instead", path.display(), source.display());
+            Ok(None)
+        }
+    }
+}
+
+/// Loads the cooked level if it is up to date and converts the Ogmo level otherwise.
+pub fn load_level(base_path: &Path, source: &Path, tiles_per_row: u32, autotile: &AutotileRules) -> Result<LevelData, Error> {
+    match load_cooked_level(base_path, source, tiles_per_row)? {
+        Some(level) => Ok(level),
+        None => LevelData::load(&Level::from_file(source)?, tiles_per_row, autotile)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::autotile::TilesetAutotileRules;
+    use crate::texture::TILE_EXTRUSION;
+
+    const LAYOUT: TileLayout = TileLayout {
+        tile_width: 16,
+        tile_height: 16,
+        margin_x: 0,
+        margin_y: 0,
+        spacing_x: 0,
+        spacing_y: 0,
+        extrusion: TILE_EXTRUSION
+    };
+
+    /// An empty directory that only this test writes to.
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("{}-{}-{}", env!("CARGO_PKG_NAME"), name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn assets() -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("assets")
+    }
+
+    /// A 4x2 tileset in which every pixel differs from its neighbours.
+    fn write_tileset(path: &Path, seed: u8) {
+        image::RgbaImage::from_fn(64, 32, |x, y| image::Rgba([x as u8 * 4, y as u8 * 8, (x ^ y) as u8 ^ seed, 255]))
+            .save(path)
+            .unwrap();
+    }
+
+    /// A base path with the project, the autotile rules and level1.
+    fn copy_level(dir: &Path) -> PathBuf {
+        create_dir_all(dir.join("levels")).unwrap();
+        std::fs::copy(assets().join("project.ogmo"), dir.join("project.ogmo")).unwrap();
+        std::fs::copy(assets().join("autotile.json"), dir.join("autotile.json")).unwrap();
+        let source = dir.join("levels/level1.json");
+        std::fs::copy(assets().join("levels/level1.json"), &source).unwrap();
+        source
+    }
+
+    fn autotile_rules(dir: &Path) -> AutotileRules {
+        TilesetAutotileRules::load(&dir.join("autotile.json")).unwrap().get("default").cloned().unwrap()
+    }
+
+    fn assert_same_level(a: &LevelData, b: &LevelData) {
+        assert_eq!((a.grid.width(), a.grid.height()), (b.grid.width(), b.grid.height()));
+        for y in 0..a.grid.height() as i32 {
+            for x in 0..a.grid.width() as i32 {
+                assert_eq!((a.grid.get(x, y), a.grid.transform(x, y)), (b.grid.get(x, y), b.grid.transform(x, y)), "Tile ({}, {})", x, y);
+            }
+        }
+        assert_eq!(a.spawns.len(), b.spawns.len());
+        for (a, b) in a.spawns.iter().zip(&b.spawns) {
+            assert_eq!((&a.name, a.position, &a.nodes), (&b.name, b.position, &b.nodes));
+        }
+    }
+
+    #[test]
+    fn tileset_round_trip() {
+        let dir = temp_dir("tileset-round-trip");
+        let source = dir.join("tiles.png");
+        write_tileset(&source, 0);
+        cook_tileset(&dir, &source, &LAYOUT).unwrap();
+
+        let cooked = load_cooked_tileset(&dir, &source, &LAYOUT).unwrap().unwrap();
+        let parsed = TextureData::parse_tileset(&source, &LAYOUT, Some(TILESET_FILTER)).unwrap();
+        assert_eq!((cooked.width(), cooked.height(), cooked.depth(), cooked.mipmaps()), (parsed.width(), parsed.height(), parsed.depth(), parsed.mipmaps()));
+        assert_eq!((cooked.depth_x(), cooked.view_dimension()), (Some(4), parsed.view_dimension()));
+        assert!(cooked.as_bytes() == parsed.as_bytes());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn stale_tilesets_are_not_loaded() {
+        let dir = temp_dir("stale-tileset");
+        let source = dir.join("tiles.png");
+        assert!(load_cooked_tileset(&dir, &source, &LAYOUT).unwrap().is_none());
+        write_tileset(&source, 0);
+        cook_tileset(&dir, &source, &LAYOUT).unwrap();
+        assert!(load_cooked_tileset(&dir, &source, &TileLayout { extrusion: 0, ..LAYOUT }).unwrap().is_none());
+        write_tileset(&source, 1);
+        assert!(load_cooked_tileset(&dir, &source, &LAYOUT).unwrap().is_none());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn level_round_trip() {
+        let dir = temp_dir("level-round-trip");
+        let source = copy_level(&dir);
+        let rules = autotile_rules(&dir);
+        cook_level(&dir, &source, 8, &rules).unwrap();
+
+        let cooked = load_cooked_level(&dir, &source, 8).unwrap().unwrap();
+        assert_same_level(&cooked, &LevelData::load(&Level::from_file(&source).unwrap(), 8, &rules).unwrap());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn changed_levels_fall_back_to_the_source() {
+        let dir = temp_dir("stale-level");
+        let source = copy_level(&dir);
+        let rules = autotile_rules(&dir);
+        cook_level(&dir, &source, 8, &rules).unwrap();
+        assert!(load_cooked_level(&dir, &source, 16).unwrap().is_none());
+
+        let mut json = std::fs::read_to_string(&source).unwrap();
+        json.push('\n');
+        write(&source, json).unwrap();
+        assert!(load_cooked_level(&dir, &source, 8).unwrap().is_none());
+        let level = load_level(&dir, &source, 8, &rules).unwrap();
+        assert_same_level(&level, &LevelData::load(&Level::from_file(&source).unwrap(), 8, &rules).unwrap());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn autotile_rules_are_optional() {
+        let dir = temp_dir("optional-autotile");
+        let source = copy_level(&dir);
+        let rules = autotile_rules(&dir);
+        cook_level(&dir, &source, 8, &rules).unwrap();
+
+        std::fs::remove_file(dir.join("autotile.json")).unwrap();
+        assert!(load_cooked_level(&dir, &source, 8).unwrap().is_none());
+        cook_level(&dir, &source, 8, &AutotileRules::default()).unwrap();
+        assert!(load_cooked_level(&dir, &source, 8).unwrap().is_some());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+}
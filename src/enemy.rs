@@ -0,0 +1,156 @@
+use glam::*;
+use serde::Deserialize;
+use crate::catalog::Catalog;
+use crate::physics::{Body, Aabb};
+use crate::player::Player;
+use crate::tilemap::TileGrid;
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Behavior {
+    /// Walks in one direction and turns around at walls and, if `turn_at_ledges` is set, at ledges.
+    Walker { speed: f32, #[serde(default = "default_true")] turn_at_ledges: bool },
+    /// Flies horizontally between walls while bobbing along a sine wave.
+    Flyer { speed: f32, amplitude: f32, frequency: f32 },
+    /// Patrols like a walker until the player comes within `range`, then runs towards them.
+    Chaser { speed: f32, chase_speed: f32, range: f32 }
+}
+
+fn default_true() -> bool {
+    true
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct Archetype {
+    pub behavior: Behavior,
+    pub size: [f32; 2],
+    /// Tile of the tileset used as sprite, negative values draw a plain rectangle.
+    #[serde(default = "default_tile")]
+    pub tile: i32,
+    #[serde(default = "default_color")]
+    pub color: [f32; 4],
+    #[serde(default = "default_true")]
+    pub stompable: bool,
+    #[serde(default = "default_damage")]
+    pub damage: u32,
+    /// Score awarded for stomping the enemy.
+    #[serde(default)]
+    pub score: u32
+}
+
+fn default_tile() -> i32 {
+    -1
+}
+
+fn default_color() -> [f32; 4] {
+    [1.0; 4]
+}
+
+fn default_damage() -> u32 {
+    1
+}
+
+pub type Archetypes = Catalog<Archetype>;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Interaction {
+    None,
+    Stomped,
+    Damaged
+}
+
+#[derive(Clone)]
+pub struct Enemy {
+    pub archetype: Archetype,
+    pub body: Body,
+    pub alive: bool,
+    direction: f32,
+    origin: Vec2,
+    time: f32
+}
+
+impl Enemy {
+
+    pub fn new(archetype: &Archetype, spawn: Vec2) -> Self {
+        let body = Body::standing_at(spawn, Vec2::from(archetype.size));
+        Self {
+            archetype: archetype.clone(),
+            origin: body.position,
+            body,
+            alive: true,
+            direction: -1.0,
+            time: 0.0
+        }
+    }
+
+    pub fn update(&mut self, grid: &TileGrid, player: &Player, dt: f32) {
+        if !self.alive {
+            return;
+        }
+        self.time += dt;
+        match self.archetype.behavior {
+            Behavior::Walker { speed, turn_at_ledges } => {
+                self.patrol(grid, speed, turn_at_ledges);
+                self.body.apply_gravity(dt);
+                self.body.move_and_collide(grid, dt);
+            }
+            Behavior::Flyer { speed, amplitude, frequency } => {
+                if self.body.contacts.wall() {
+                    self.direction = -self.direction;
+                }
+                let offset = amplitude * f32::sin(self.time * frequency * std::f32::consts::TAU);
+                let target_y = self.origin.y + offset;
+                self.body.velocity = vec2(self.direction * speed, (target_y - self.body.position.y) / dt.max(f32::EPSILON));
+                self.body.move_and_collide(grid, dt);
+            }
+            Behavior::Chaser { speed, chase_speed, range } => {
+                let to_player = player.body.center() - self.body.center();
+                if to_player.length() <= range && to_player.x.abs() > 0.1 {
+                    self.direction = to_player.x.signum();
+                    let blocked = self.body.contacts.floor && !self.body.has_ground_ahead(grid, self.direction);
+                    self.body.velocity.x = if blocked { 0.0 } else { self.direction * chase_speed };
+                } else {
+                    self.patrol(grid, speed, true);
+                }
+                self.body.apply_gravity(dt);
+                self.body.move_and_collide(grid, dt);
+            }
+        }
+    }
+
+    fn patrol(&mut self, grid: &TileGrid, speed: f32, turn_at_ledges: bool) {
+        let at_wall = (self.direction < 0.0 && self.body.contacts.left) || (self.direction > 0.0 && self.body.contacts.right);
+        let at_ledge = turn_at_ledges && self.body.contacts.floor && !self.body.has_ground_ahead(grid, self.direction);
+        if at_wall || at_ledge {
+            self.direction = -self.direction;
+        }
+        self.body.velocity.x = self.direction * speed;
+    }
+
+    pub fn is_flying(&self) -> bool {
+        matches!(self.archetype.behavior, Behavior::Flyer { .. })
+    }
+
+    pub fn aabb(&self) -> Aabb {
+        self.body.aabb()
+    }
+
+    /// Resolves contact with the player: landing on top of a stompable enemy kills it, any other touch hurts the player.
+    pub fn interact(&mut self, player: &mut Player) -> Interaction {
+        if !self.alive || !self.aabb().overlaps(&player.body.aabb()) {
+            return Interaction::None;
+        }
+        let falling = player.body.velocity.y < 0.0;
+        let above = player.body.position.y > self.aabb().center().y;
+        if self.archetype.stompable && falling && above {
+            self.alive = false;
+            player.bounce();
+            Interaction::Stomped
+        } else if player.hurt(self.archetype.damage, self.body.center()) {
+            Interaction::Damaged
+        } else {
+            Interaction::None
+        }
+    }
+
+}
@@ -7,6 +7,10 @@ use imgui_wgpu::{Renderer, RendererConfig};
 use imgui::FontSource;
 use wgpu::{RenderPass, BackendBit};
 use winit::dpi::{Size, PhysicalSize};
+use crate::input::Input;
+use crate::pipeline::{PipelineBuilder, PipelineCache};
+use std::cell::RefCell;
+use std::rc::Rc;
 
 pub struct Display {
     pub window: Window,
@@ -15,6 +19,10 @@ pub struct Display {
     pub queue: wgpu::Queue,
     pub sc_desc: wgpu::SwapChainDescriptor,
     pub swap_chain: wgpu::SwapChain,
+    pub input: Input,
+    /// The optional features the device was created with, e.g. which compressed texture formats can be sampled.
+    pub features: wgpu::Features,
+    pipelines: RefCell<PipelineCache>,
 }
 
 impl Display {
@@ -47,11 +55,17 @@ impl Display {
 
         std::env::set_var("BACKEND", format!("{:?}", adapter.get_info().backend));
 
+        // Request every texture compression the adapter has, BC1 to BC5 textures get decompressed on the cpu without it
+        let features = adapter.features() & (wgpu::Features::TEXTURE_COMPRESSION_BC
+            | wgpu::Features::TEXTURE_COMPRESSION_ETC2
+            | wgpu::Features::TEXTURE_COMPRESSION_ASTC_LDR);
+        log::info!("Texture compression features: {:?}", features);
+
         let (device, queue) = adapter
             .request_device(
                 &wgpu::DeviceDescriptor {
                     label: None,
-                    features: wgpu::Features::empty(),
+                    features,
                     limits: wgpu::Limits::default(),
                 },
                 None, // Trace path
@@ -75,9 +89,17 @@ impl Display {
             queue,
             sc_desc,
             swap_chain,
+            input: Input::default(),
+            features,
+            pipelines: RefCell::new(PipelineCache::default()),
         })
     }
 
+    /// The cached pipeline for `builder`'s key, built on first use.
+    pub fn pipeline(&self, builder: &PipelineBuilder) -> Rc<wgpu::RenderPipeline> {
+        self.pipelines.borrow_mut().get_or_build(&self.device, builder)
+    }
+
     fn resize(&mut self, width: u32, height: u32) {
         self.sc_desc.width = width;
         self.sc_desc.height = height;
@@ -94,11 +116,27 @@ macro_rules! include_spirv_out {
     };
 }
 
+/// Like `include_spirv_out!` for `.wgsl` shaders, which `build.rs` validates and copies to `OUT_DIR`.
+#[macro_export]
+macro_rules! include_wgsl_out {
+    ($file:expr) => {
+        {
+            wgpu::ShaderModuleDescriptor {
+                label: Some($file),
+                source: wgpu::ShaderSource::Wgsl(std::borrow::Cow::Borrowed(include_str!(concat!(env!("OUT_DIR"), "/", file!() , "/../", $file)))),
+                flags: wgpu::ShaderFlags::VALIDATION,
+            }
+        }
+    };
+}
+
 pub trait Game: 'static + Sized {
     fn init(display: &Display) -> Result<Self, Error>;
     fn resize(&mut self, display: &Display, width: u32, height: u32);
     fn update(&mut self, display: &Display, dt: Duration);
     fn render(&mut self, display: &mut Display, encoder: &mut wgpu::CommandEncoder, frame: &wgpu::TextureView, ui: Option<&imgui::Ui>);
+    #[allow(unused_variables)]
+    fn exit(&mut self, display: &Display) {}
 }
 
 pub async fn run<G: Game>() -> Result<(), Error> {
@@ -132,6 +170,7 @@ pub async fn run<G: Game>() -> Result<(), Error> {
 
         match event {
             Event::Resumed => is_resumed = true,
+            Event::LoopDestroyed => game.exit(&display),
             Event::Suspended => is_resumed = false,
             Event::RedrawRequested(wid) => {
                 if wid == display.window.id() {
@@ -140,6 +179,7 @@ pub async fn run<G: Game>() -> Result<(), Error> {
                     last_update = now;
 
                     game.update(&display, dt);
+                    display.input.end_frame();
 
 
                     if let Some(imgui) = imgui.as_mut() {
@@ -189,7 +229,20 @@ pub async fn run<G: Game>() -> Result<(), Error> {
                 if window_id == display.window.id() {
                     match event {
                         WindowEvent::CloseRequested => *control_flow = ControlFlow::Exit,
-                        WindowEvent::Focused(f) => is_focused = f,
+                        WindowEvent::Focused(f) => {
+                            is_focused = f;
+                            if !f {
+                                display.input.clear();
+                            }
+                        }
+                        WindowEvent::KeyboardInput {
+                            input: KeyboardInput {
+                                state,
+                                virtual_keycode: Some(key),
+                                ..
+                            },
+                            ..
+                        } => display.input.handle_key(key, state),
                         WindowEvent::ScaleFactorChanged { new_inner_size, .. } => {
                             display.resize(new_inner_size.width, new_inner_size.height);
                             game.resize(&mut display, new_inner_size.width, new_inner_size.height);
@@ -307,4 +360,22 @@ impl ImguiWrapper {
         })
     }
 
-}
\ No newline at end of file
+}
+#[cfg(test)]
+pub mod tests {
+    use futures::executor::block_on;
+
+    /// A device without a surface, `None` on machines without an adapter so gpu tests can skip.
+    pub fn device() -> Option<(wgpu::Device, wgpu::Queue)> {
+        let instance = wgpu::Instance::new(wgpu::BackendBit::PRIMARY);
+        let adapter = block_on(instance.request_adapter(&wgpu::RequestAdapterOptions {
+            power_preference: wgpu::PowerPreference::default(),
+            compatible_surface: None,
+        }))?;
+        block_on(adapter.request_device(&wgpu::DeviceDescriptor {
+            label: None,
+            features: wgpu::Features::empty(),
+            limits: wgpu::Limits::default(),
+        }, None)).ok()
+    }
+}
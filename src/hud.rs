@@ -0,0 +1,135 @@
+use glam::*;
+use anyhow::*;
+use crate::buffer::{UniformBuffer, UpdateUniformBuffer, BufferOptions};
+use crate::framework::Display;
+use crate::player::MAX_HEALTH;
+use crate::atlas::{Atlas, AtlasRegion};
+use crate::sprite::{Sprite, SpriteBatch, SpriteRenderer};
+use crate::world::{World, PLAYER_COLOR};
+
+/// The HUD is laid out for this window height and scaled to the actual one.
+const REFERENCE_HEIGHT: f32 = 720.0;
+const UNIT: f32 = 32.0;
+const MARGIN: f32 = 0.75;
+const DIGIT_WIDTH: f32 = 0.6;
+const DIGIT_SPACING: f32 = 0.25;
+const SEGMENT: f32 = 0.14;
+
+const HEART_COLOR: [f32; 4] = [0.85, 0.15, 0.2, 1.0];
+const EMPTY_HEART_COLOR: [f32; 4] = [0.25, 0.1, 0.1, 0.6];
+const TEXT_COLOR: [f32; 4] = [1.0, 1.0, 1.0, 1.0];
+const SHADOW_COLOR: [f32; 4] = [0.0, 0.0, 0.0, 0.5];
+
+/// Segments of a seven segment display in the order a to g.
+const SEGMENTS: [([f32; 2], [f32; 2]); 7] = [
+    ([0.0, 0.0], [DIGIT_WIDTH, SEGMENT]),
+    ([DIGIT_WIDTH - SEGMENT, 0.0], [SEGMENT, 0.5]),
+    ([DIGIT_WIDTH - SEGMENT, 0.5], [SEGMENT, 0.5]),
+    ([0.0, 1.0 - SEGMENT], [DIGIT_WIDTH, SEGMENT]),
+    ([0.0, 0.5], [SEGMENT, 0.5]),
+    ([0.0, 0.0], [SEGMENT, 0.5]),
+    ([0.0, 0.5 - SEGMENT * 0.5], [DIGIT_WIDTH, SEGMENT]),
+];
+
+const DIGITS: [u8; 10] = [
+    0b0111111, 0b0000110, 0b1011011, 0b1001111, 0b1100110,
+    0b1101101, 0b1111101, 0b0000111, 0b1111111, 0b1101111
+];
+
+/// Screen space overlay showing health, lives and score. Drawn with the sprite pipeline in pixel coordinates,
+/// by a renderer that samples the sprite atlas.
+pub struct Hud {
+    camera_buffer: UniformBuffer<Mat4>,
+    batch: SpriteBatch,
+    size: Vec2,
+    gem: AtlasRegion
+}
+
+impl Hud {
+
+    pub fn new(display: &Display, atlas: &Atlas) -> Result<Self, Error> {
+        Ok(Self {
+            camera_buffer: UniformBuffer::with_options(&display.device, BufferOptions::default().label("Hud Camera Buffer")),
+            batch: SpriteBatch::new(&display.device, 128),
+            size: vec2(display.sc_desc.width as f32, display.sc_desc.height as f32),
+            gem: *atlas.get("gem").context("The sprite atlas has no gem")?
+        })
+    }
+
+    pub fn resize(&mut self, width: u32, height: u32) {
+        self.size = vec2(width as f32, height as f32);
+    }
+
+    fn unit(&self) -> f32 {
+        UNIT * self.size.y / REFERENCE_HEIGHT
+    }
+
+    fn rect(&mut self, position: Vec2, size: Vec2, color: [f32; 4]) {
+        let unit = self.unit();
+        self.batch.push(Sprite::rect(position * unit, size * unit, color));
+    }
+
+    /// Draws `region` with its aspect ratio and `height` units high.
+    fn region(&mut self, position: Vec2, height: f32, region: AtlasRegion) {
+        let unit = self.unit();
+        let size = vec2(height * region.size.x as f32 / region.size.y as f32, height) * unit;
+        // The y axis points down here, a negative height keeps the sprite upright
+        self.batch.push(Sprite::region(position * unit + vec2(0.0, size.y), size * vec2(1.0, -1.0), &region));
+    }
+
+    fn digit(&mut self, position: Vec2, digit: u32, color: [f32; 4]) {
+        let mask = DIGITS[digit as usize % DIGITS.len()];
+        for (i, (offset, size)) in SEGMENTS.iter().enumerate() {
+            if mask & (1 << i) != 0 {
+                self.rect(position + Vec2::from(*offset), Vec2::from(*size), color);
+            }
+        }
+    }
+
+    /// Draws `number` with a drop shadow, starting at `position` if `align_right` is false, or ending there otherwise.
+    fn number(&mut self, position: Vec2, number: u32, align_right: bool) {
+        let digits = number.to_string();
+        let advance = DIGIT_WIDTH + DIGIT_SPACING;
+        let width = digits.len() as f32 * advance - DIGIT_SPACING;
+        let start = if align_right { position.x - width } else { position.x };
+        for (i, c) in digits.chars().enumerate() {
+            let digit = c.to_digit(10).unwrap_or(0);
+            let position = vec2(start + i as f32 * advance, position.y);
+            self.digit(position + vec2(0.06, 0.06), digit, SHADOW_COLOR);
+            self.digit(position, digit, TEXT_COLOR);
+        }
+    }
+
+    pub fn prepare(&mut self, display: &Display, world: &World) {
+        self.batch.clear();
+
+        for i in 0..MAX_HEALTH {
+            let color = if i < world.player.health { HEART_COLOR } else { EMPTY_HEART_COLOR };
+            self.rect(vec2(MARGIN + i as f32 * 1.25, MARGIN), vec2(1.0, 1.0), color);
+        }
+
+        let lives_y = MARGIN + 1.5;
+        self.rect(vec2(MARGIN, lives_y), vec2(0.8, 0.9), PLAYER_COLOR);
+        self.rect(vec2(MARGIN + 1.05, lives_y + 0.35), vec2(0.3, 0.3), TEXT_COLOR);
+        self.number(vec2(MARGIN + 1.6, lives_y), world.lives, false);
+
+        let right = self.size.x / self.unit() - MARGIN;
+        let score_width = world.score.to_string().len() as f32 * (DIGIT_WIDTH + DIGIT_SPACING);
+        self.region(vec2(right - score_width - 1.0, MARGIN), 1.0, self.gem);
+        self.number(vec2(right, MARGIN), world.score, true);
+
+        let fade = world.fade();
+        if fade > 0.0 {
+            self.batch.push(Sprite::rect(Vec2::ZERO, self.size, [0.0, 0.0, 0.0, fade]));
+        }
+
+        self.batch.upload(&display.device, &display.queue);
+        display.queue.update_uniform_buffer(&self.camera_buffer,
+            &Mat4::orthographic_rh(0.0, self.size.x, self.size.y, 0.0, 0.0, 100.0));
+    }
+
+    pub fn render<'a>(&'a self, renderer: &'a SpriteRenderer, render_pass: &mut wgpu::RenderPass<'a>) {
+        renderer.render(render_pass, &self.batch, &self.camera_buffer);
+    }
+
+}
@@ -0,0 +1,46 @@
+use std::collections::HashSet;
+use winit::event::{ElementState, VirtualKeyCode};
+
+#[derive(Default)]
+pub struct Input {
+    held: HashSet<VirtualKeyCode>,
+    pressed: HashSet<VirtualKeyCode>
+}
+
+impl Input {
+
+    pub fn handle_key(&mut self, key: VirtualKeyCode, state: ElementState) {
+        match state {
+            ElementState::Pressed => {
+                if self.held.insert(key) {
+                    self.pressed.insert(key);
+                }
+            }
+            ElementState::Released => {
+                self.held.remove(&key);
+            }
+        }
+    }
+
+    pub fn is_held(&self, key: VirtualKeyCode) -> bool {
+        self.held.contains(&key)
+    }
+
+    pub fn is_pressed(&self, key: VirtualKeyCode) -> bool {
+        self.pressed.contains(&key)
+    }
+
+    pub fn axis(&self, negative: VirtualKeyCode, positive: VirtualKeyCode) -> f32 {
+        (self.is_held(positive) as i32 - self.is_held(negative) as i32) as f32
+    }
+
+    pub fn clear(&mut self) {
+        self.held.clear();
+        self.pressed.clear();
+    }
+
+    pub fn end_frame(&mut self) {
+        self.pressed.clear();
+    }
+
+}
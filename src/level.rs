@@ -0,0 +1,111 @@
+use glam::*;
+use anyhow::*;
+use ogmo3::{Level, Layer};
+use crate::tilemap::{TileGrid, TRANSFORM_BITS};
+use crate::autotile::AutotileRules;
+
+/// Optional grid layer that mirrors and rotates the tiles below it. Every cell holds the transform bits
+/// of `tilemap` as a digit from `0` to `7`, so the layer is painted with the values 1 = flip x, 2 = flip y,
+/// 4 = rotate by 90° and their sums.
+const TRANSFORM_LAYER: &str = "tile_transforms";
+
+pub struct EntitySpawn {
+    pub name: String,
+    pub position: Vec2,
+    pub nodes: Vec<Vec2>
+}
+
+pub struct LevelData {
+    pub grid: TileGrid,
+    pub spawns: Vec<EntitySpawn>
+}
+
+fn get_tile_id(coords: ogmo3::Vec2<i32>, tiles_per_row: i32) -> Option<u32> {
+    if !(0..tiles_per_row).contains(&coords.x) {
+        return None;
+    }
+    Some((coords.x + tiles_per_row as i32 * coords.y) as u32)
+}
+
+impl LevelData {
+
+    /// Converts the Ogmo levels into world space: one unit per tile with the y axis pointing up.
+    /// Entity positions and nodes are taken as their bottom center, which is where all entity origins are set in the project.
+    /// Cells of the terrain layer named by `autotile` replace the tiles below them.
+    pub fn load(level: &Level, tiles_per_row: u32, autotile: &AutotileRules) -> Result<Self, Error> {
+        let tile_layer = level.layers.iter()
+            .find_map(|layer| match layer {
+                Layer::TileCoords(layer) => Some(layer),
+                _ => None
+            })
+            .context("The level has no tile layer")?;
+
+        let mut grid = TileGrid::new(tile_layer.grid_cells_x as u32, tile_layer.grid_cells_y as u32);
+        for tile in tile_layer.unpack() {
+            if let Some(coords) = tile.grid_coords {
+                let id = get_tile_id(coords, tiles_per_row as i32).context("Tile is outside of the tileset")?;
+                grid.set(tile.grid_position.x, tile_layer.grid_cells_y - 1 - tile.grid_position.y, Some(id));
+            }
+        }
+
+        let terrain_layer = level.layers.iter()
+            .find_map(|layer| match layer {
+                Layer::Grid(layer) if layer.name == autotile.layer => Some(layer),
+                _ => None
+            });
+        if let Some(layer) = terrain_layer {
+            let mut terrains = vec![None; (grid.width() * grid.height()) as usize];
+            for cell in layer.unpack() {
+                let (x, y) = (cell.grid_position.x, layer.grid_cells_y - 1 - cell.grid_position.y);
+                if (0..grid.width() as i32).contains(&x) && (0..grid.height() as i32).contains(&y) {
+                    terrains[(x + y * grid.width() as i32) as usize] = autotile.terrain(&cell.value)?;
+                }
+            }
+            autotile.apply(&mut grid, &terrains)?;
+        }
+
+        let transform_layer = level.layers.iter()
+            .find_map(|layer| match layer {
+                Layer::Grid(layer) if layer.name == TRANSFORM_LAYER => Some(layer),
+                _ => None
+            });
+        if let Some(layer) = transform_layer {
+            for cell in layer.unpack() {
+                let transform = cell.value.parse::<u8>()
+                    .ok()
+                    .filter(|transform| transform & !TRANSFORM_BITS == 0)
+                    .with_context(|| format!("Invalid tile transform {:?}", cell.value))?;
+                grid.set_transform(cell.grid_position.x, layer.grid_cells_y - 1 - cell.grid_position.y, transform);
+            }
+        }
+
+        let mut spawns = Vec::new();
+        for layer in &level.layers {
+            if let Layer::Entity(layer) = layer {
+                let cell = vec2(layer.grid_cell_width as f32, layer.grid_cell_height as f32);
+                let to_world = |x: f32, y: f32| vec2(x / cell.x, grid.height() as f32 - y / cell.y);
+                for entity in &layer.entities {
+                    spawns.push(EntitySpawn {
+                        name: entity.name.clone(),
+                        position: to_world(entity.x, entity.y),
+                        nodes: entity.nodes
+                            .iter()
+                            .flatten()
+                            .map(|node| to_world(node.x, node.y))
+                            .collect()
+                    });
+                }
+            }
+        }
+
+        Ok(Self {
+            grid,
+            spawns
+        })
+    }
+
+    pub fn spawns_named<'a>(&'a self, name: &'a str) -> impl Iterator<Item=&'a EntitySpawn> + 'a {
+        self.spawns.iter().filter(move |spawn| spawn.name == name)
+    }
+
+}
@@ -1,45 +1,199 @@
-use wgpu::util::DeviceExt;
 use std::time::Duration;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use anyhow::*;
 use imgui::Condition;
 use imgui::im_str;
 use glam::*;
 use crate::framework::{run, Display, Game};
-use wgpu::{BlendFactor, BlendOperation};
-use ogmo3::{Level, Layer, Project};
+use ogmo3::Project;
 use crate::camera::Camera;
-use crate::buffer::{UniformBuffer, UpdateUniformBuffer, BindUniformBuffer};
-use crate::texture::{TextureData, MipMaps};
+use crate::buffer::{UniformBuffer, UpdateUniformBuffer, BufferOptions};
+use crate::texture::{TextureData, TileLayout, TILE_EXTRUSION};
+use crate::mipgen::MipGenerator;
+use crate::compressed::CompressedTextureData;
+use crate::level::LevelData;
+use crate::enemy::Archetypes;
+use crate::platform::PlatformKinds;
+use crate::collectible::CollectibleKinds;
+use crate::tilemap::TilesetProperties;
+use crate::autotile::{AutotileRules, TilesetAutotileRules};
+use crate::world::{World, Definitions};
+use crate::hud::Hud;
+use crate::atlas::AtlasBuilder;
+use crate::save::SaveGame;
+use winit::event::VirtualKeyCode;
+use winit::window::Fullscreen;
+use crate::sprite::{SpriteRenderer, SpriteBatch};
+use crate::chunk::ChunkedTilemap;
+use crate::parallax::{ParallaxBackground, LevelBackgrounds};
+use crate::assert_buffer_set;
 
 mod framework;
 mod camera;
 mod buffer;
+mod bindings;
+mod shaders;
+mod mesh;
+mod pipeline;
 mod texture;
-
-
-#[repr(C)]
-#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
-struct Vertex {
-    position: glam::Vec2,
-    tex_coords: glam::Vec2,
-}
+mod mipmap;
+mod mipgen;
+mod pixel;
+mod compressed;
+mod atlas;
+mod cook;
+mod input;
+mod tilemap;
+mod chunk;
+mod autotile;
+mod parallax;
+mod physics;
+mod level;
+mod player;
+mod enemy;
+mod world;
+mod sprite;
+mod catalog;
+mod platform;
+mod collectible;
+mod hud;
+mod checkpoint;
+mod save;
+
+// Every program binds `camera_buffer` as set 1
+assert_buffer_set!(shader::set1, wgpu::BufferBindingType::Uniform);
+assert_buffer_set!(sprite::set1, wgpu::BufferBindingType::Uniform);
+assert_buffer_set!(parallax::set1, wgpu::BufferBindingType::Uniform);
 
 struct JumpAndRun {
-    render_pipeline: wgpu::RenderPipeline,
-    vertex_buffer: wgpu::Buffer,
-    index_buffer: wgpu::Buffer,
-    num_indices: u32,
+    background: ParallaxBackground,
+    tilemap: ChunkedTilemap,
+    debug_grid: bool,
+    pixel_art: bool,
     camera: Camera,
     camera_buffer: UniformBuffer<Mat4>,
-    diffuse_bind_group: wgpu::BindGroup,
+    sprite_renderer: SpriteRenderer,
+    sprite_batch: SpriteBatch,
+    hud_renderer: SpriteRenderer,
+    world: World,
+    hud: Hud,
+    camera_follow: bool,
+    save: SaveGame,
+    save_path: Option<PathBuf>,
+    /// Unlocked when `LEVEL` is completed.
+    next_level: Option<String>,
+}
+
+const MAX_TIME_STEP: f32 = 1.0 / 30.0;
+const LEVEL: &str = "level1";
+/// Generates the tileset mip chain with a blit pass on the gpu instead of the filtered cpu path.
+const GPU_MIPMAPS_FLAG: &str = "--gpu-mipmaps";
+
+/// The image of the first tileset of the project and where its tiles are.
+fn tile_layout(base_path: &Path, project: &Project) -> Result<(PathBuf, TileLayout), Error> {
+    let ts = project.tilesets.first().context("The project has no tileset")?;
+    Ok((base_path.join(&ts.path), TileLayout {
+        tile_width: ts.tile_width as u32,
+        tile_height: ts.tile_height as u32,
+        margin_x: ts.tile_margin_x as u32,
+        margin_y: ts.tile_margin_y as u32,
+        spacing_x: ts.tile_separation_x as u32,
+        spacing_y: ts.tile_separation_y as u32,
+        extrusion: TILE_EXTRUSION
+    }))
 }
 
-fn get_tile_id(coords: ogmo3::Vec2<i32>, tiles_per_row: i32) -> Option<u32> {
-    if !(0..tiles_per_row).contains(&coords.x) {
-        return None;
+/// The autotile rules of the first tileset of the project, none if there is no `autotile.json` or it has no entry for it.
+fn autotile_rules(base_path: &Path, project: &Project) -> Result<AutotileRules, Error> {
+    let ts = project.tilesets.first().context("The project has no tileset")?;
+    let path = base_path.join("autotile.json");
+    if !path.exists() {
+        return Ok(AutotileRules::default());
     }
-    Some((coords.x + tiles_per_row as i32 * coords.y) as u32)
+    Ok(TilesetAutotileRules::load(&path)?
+        .get(&ts.label)
+        .cloned()
+        .unwrap_or_default())
+}
+
+/// Prefers a pre-compressed `.ktx2` or `.dds` array texture next to the tileset image, with one layer per
+/// extruded tile, then an up to date cooked tileset and finally slices the image itself.
+/// Running with `--gpu-mipmaps` skips the cooked tileset and lets the gpu fill the mip chain of the slices.
+/// Returns the view of the tile array, the number of tiles per row of the image and the number of tiles.
+fn load_tileset(display: &Display, base_path: &Path, path: &Path, layout: &TileLayout) -> Result<(wgpu::TextureView, u32, u32), Error> {
+    let usage = wgpu::TextureUsage::SAMPLED | wgpu::TextureUsage::COPY_DST;
+    let compressed = ["ktx2", "dds"].iter().map(|ext| path.with_extension(ext)).find(|path| path.exists());
+    if let Some(compressed) = compressed {
+        let (width, height) = image::image_dimensions(path)?;
+        let (tiles_per_row, _) = layout.tile_count(width, height);
+        let data = CompressedTextureData::load(&compressed, display.features)?;
+        let texture = data.to_texture(&display.device, &display.queue, display.features, usage)?;
+        let view = texture.create_view(&wgpu::TextureViewDescriptor {
+            dimension: Some(data.view_dimension()),
+            ..Default::default()
+        });
+        return Ok((view, tiles_per_row, data.layers()));
+    }
+
+    let gpu_mipmaps = std::env::args().any(|arg| arg == GPU_MIPMAPS_FLAG);
+    if !gpu_mipmaps {
+        if let Some(td) = cook::load_cooked_tileset(base_path, path, layout)? {
+            let texture = td.to_texture(&display.device, &display.queue, wgpu::TextureFormat::Rgba8UnormSrgb, usage)?;
+            return Ok((td.create_view(&texture), td.depth_x().unwrap(), td.layers()));
+        }
+    }
+
+    let filter = if gpu_mipmaps { None } else { Some(cook::TILESET_FILTER) };
+    let td = TextureData::parse_tileset(&path.to_path_buf(), layout, filter)?;
+    let texture = if gpu_mipmaps {
+        let generator = MipGenerator::new(&display.device, wgpu::TextureFormat::Rgba8UnormSrgb);
+        td.to_texture_with_gpu_mipmaps(&display.device, &display.queue, wgpu::TextureUsage::SAMPLED, &generator)?
+    } else {
+        td.to_texture(&display.device, &display.queue, wgpu::TextureFormat::Rgba8UnormSrgb, usage)?
+    };
+    Ok((td.create_view(&texture), td.depth_x().unwrap(), td.layers()))
+}
+
+/// The names of the levels in `assets/levels`, in the order they are played.
+fn level_names(base_path: &Path) -> Result<Vec<String>, Error> {
+    let mut names = Vec::new();
+    for entry in std::fs::read_dir(base_path.join("levels"))? {
+        let path = entry?.path();
+        if path.extension().and_then(|ext| ext.to_str()) == Some("json") {
+            names.extend(path.file_stem().and_then(|name| name.to_str()).map(str::to_string));
+        }
+    }
+    names.sort();
+    Ok(names)
+}
+
+/// Writes the cooked tileset and levels to `assets/cooked`.
+fn cook_assets() -> Result<(), Error> {
+    let base_path = PathBuf::from("./assets/");
+    let project = Project::from_file(base_path.join("project.ogmo"))?;
+    let (tileset_path, layout) = tile_layout(&base_path, &project)?;
+    cook::cook_tileset(&base_path, &tileset_path, &layout)?;
+
+    let (width, height) = image::image_dimensions(&tileset_path)?;
+    let (tiles_per_row, _) = layout.tile_count(width, height);
+    let autotile = autotile_rules(&base_path, &project)?;
+    for name in level_names(&base_path)? {
+        cook::cook_level(&base_path, &base_path.join(format!("levels/{}.json", name)), tiles_per_row, &autotile)?;
+    }
+    Ok(())
+}
+
+impl JumpAndRun {
+
+    fn write_save(&mut self, display: &Display) {
+        self.save.settings.fullscreen = display.window.fullscreen().is_some();
+        if let Some(path) = &self.save_path {
+            if let Err(err) = self.save.save(path) {
+                log::error!("Failed to write save game: {:?}", err);
+            }
+        }
+    }
+
 }
 
 impl Game for JumpAndRun {
@@ -47,221 +201,124 @@ impl Game for JumpAndRun {
     fn init(display: &Display) -> Result<Self, Error> {
         display.window.set_title(&*format!("Jump and Run - Version {} ({})", env!("CARGO_PKG_VERSION"), std::env::var("BACKEND")?));
 
-        let vs_module = display.device.create_shader_module(&include_spirv_out!("shader.vert.spv"));
-        let fs_module = display.device.create_shader_module(&include_spirv_out!("shader.frag.spv"));
-
         let camera = Camera {
             scale: 13.0,
             aspect: display.sc_desc.width as f32 / display.sc_desc.height as f32,
             position: glam::vec2(16.0, 11.0)
         };
 
-        let camera_buffer = UniformBuffer::<Mat4>::new(&display.device);
+        let camera_buffer = UniformBuffer::<Mat4>::with_options(&display.device, BufferOptions::default().label("Camera Buffer"));
 
         let base_path = PathBuf::from("./assets/");
         let project = Project::from_file(base_path.join("project.ogmo"))?;
-        let level = Level::from_file(base_path.join("levels/level1.json"))?;
-
-        let (tileset_texture, tiles_per_row) = project.tilesets.first().map(|ts|{
-            let td = TextureData::parse_tileset(&base_path.join(&ts.path), ts.tile_width as u32, ts.tile_height as u32).unwrap();
-            (td.to_texture(&display.device, &display.queue, wgpu::TextureFormat::Rgba8UnormSrgb, wgpu::TextureUsage::SAMPLED | wgpu::TextureUsage::COPY_DST),
-            td.depth_x().unwrap())
-        }).unwrap();
-        let tileset_texture_view = tileset_texture.create_view(&Default::default());
-
-        let pt_data = match level.layers.first().unwrap() {
-            Layer::TileCoords(layer) => {
-                let mut pt_data = TextureData::<u16>::new(layer.grid_cells_x as u32, layer.grid_cells_y as u32, 1, MipMaps::None);
-                for tile in layer.unpack() {
-                    if let Some(coords) = tile.grid_coords {
-                        *pt_data.get_pixel_mut(
-                            tile.grid_position.x as u32,
-                            tile.grid_position.y as u32, 0, 0)  = (1 + get_tile_id(coords, tiles_per_row as i32).unwrap()) as u16
-                    }
-                }
-                pt_data
-            }
-            _ => panic!("layer type not supported")
-        };
-
-        let placement_texture = pt_data.to_texture(&display.device, &display.queue, wgpu::TextureFormat::R16Uint, wgpu::TextureUsage::SAMPLED | wgpu::TextureUsage::COPY_DST);
-        let placement_texture_view = placement_texture.create_view(&wgpu::TextureViewDescriptor::default());
 
-        let diffuse_sampler = display.device.create_sampler(&wgpu::SamplerDescriptor {
-            address_mode_u: wgpu::AddressMode::ClampToEdge,
-            address_mode_v: wgpu::AddressMode::ClampToEdge,
-            address_mode_w: wgpu::AddressMode::ClampToEdge,
-            mag_filter: wgpu::FilterMode::Linear,
-            min_filter: wgpu::FilterMode::Linear,
-            mipmap_filter: wgpu::FilterMode::Linear,
-            ..Default::default()
-        });
+        let next_level = level_names(&base_path)?
+            .into_iter()
+            .skip_while(|name| name != LEVEL)
+            .nth(1);
+
+        let save_path = SaveGame::default_path();
+        let save = save_path
+            .as_deref()
+            .and_then(|path| SaveGame::load(path).unwrap_or_else(|err| {
+                log::warn!("Ignoring unreadable save game: {:?}", err);
+                None
+            }))
+            .unwrap_or_else(|| SaveGame::new(LEVEL));
+        if save.settings.fullscreen {
+            display.window.set_fullscreen(Some(Fullscreen::Borderless(display.window.current_monitor())));
+        }
 
-        let texture_bind_group_layout = display.device.create_bind_group_layout(
-            &wgpu::BindGroupLayoutDescriptor {
-                entries: &[
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 0,
-                        visibility: wgpu::ShaderStage::FRAGMENT,
-                        ty: wgpu::BindingType::Texture {
-                            multisampled: false,
-                            view_dimension: wgpu::TextureViewDimension::D2Array,
-                            sample_type: wgpu::TextureSampleType::Float { filterable: false },
-                        },
-                        count: None,
-                    },
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 1,
-                        visibility: wgpu::ShaderStage::FRAGMENT,
-                        ty: wgpu::BindingType::Texture {
-                            multisampled: false,
-                            view_dimension: wgpu::TextureViewDimension::D2,
-                            sample_type: wgpu::TextureSampleType::Uint,
-                        },
-                        count: None,
-                    },
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 2,
-                        visibility: wgpu::ShaderStage::FRAGMENT,
-                        ty: wgpu::BindingType::Sampler {
-                            comparison: false,
-                            filtering: true,
-                        },
-                        count: None,
-                    },
-                ],
-                label: Some("texture_bind_group_layout"),
-            }
-        );
-
-        let diffuse_bind_group = display.device.create_bind_group(
-            &wgpu::BindGroupDescriptor {
-                layout: &texture_bind_group_layout,
-                entries: &[
-                    wgpu::BindGroupEntry {
-                        binding: 0,
-                        resource: wgpu::BindingResource::TextureView(&tileset_texture_view),
-                    },
-                    wgpu::BindGroupEntry {
-                        binding: 1,
-                        resource: wgpu::BindingResource::TextureView(&placement_texture_view),
-                    },
-                    wgpu::BindGroupEntry {
-                        binding: 2,
-                        resource: wgpu::BindingResource::Sampler(&diffuse_sampler),
-                    },
-                ],
-                label: Some("diffuse_bind_group"),
-            }
-        );
-
-
-        let render_pipeline_layout =
-            display.device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-                label: Some("Render Pipeline Layout"),
-                bind_group_layouts: &[
-                    &texture_bind_group_layout,
-                    camera_buffer.layout()
-                ],
-                push_constant_ranges: &[],
-            });
-
-        let render_pipeline = display.device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-            label: Some("Render Pipeline"),
-            layout: Some(&render_pipeline_layout),
-            vertex: wgpu::VertexState {
-                module: &vs_module,
-                entry_point: "main",
-                buffers: &[
-                    wgpu::VertexBufferLayout {
-                        array_stride: std::mem::size_of::<Vertex>() as wgpu::BufferAddress,
-                        step_mode: wgpu::InputStepMode::Vertex,
-                        attributes: &wgpu::vertex_attr_array![0 => Float2, 1 => Float2],
-                    }
-                ],
-            },
-            fragment: Some(wgpu::FragmentState {
-                module: &fs_module,
-                entry_point: "main",
-                targets: &[wgpu::ColorTargetState {
-                    format: display.sc_desc.format,
-                    color_blend: wgpu::BlendState {
-                        src_factor: BlendFactor::SrcAlpha,
-                        dst_factor: BlendFactor::OneMinusSrcAlpha,
-                        operation: BlendOperation::Add,
-                    },
-                    alpha_blend: wgpu::BlendState {
-                        src_factor: BlendFactor::One,
-                        dst_factor: BlendFactor::One,
-                        operation: BlendOperation::Add,
-                    },
-                    write_mask: wgpu::ColorWrite::ALL,
-                }],
-            }),
-            primitive: wgpu::PrimitiveState {
-                topology: wgpu::PrimitiveTopology::TriangleList,
-                strip_index_format: None,
-                front_face: wgpu::FrontFace::Ccw,
-                cull_mode: wgpu::CullMode::Back,
-                // Setting this to anything other than Fill requires Features::NON_FILL_POLYGON_MODE
-                polygon_mode: wgpu::PolygonMode::Fill,
-            },
-            depth_stencil: None,
-            multisample: wgpu::MultisampleState {
-                count: 1,
-                mask: !0,
-                alpha_to_coverage_enabled: false,
-            },
-        });
+        let (tileset_path, layout) = tile_layout(&base_path, &project)?;
+        let (tileset_texture_view, tiles_per_row, tile_count) = load_tileset(display, &base_path, &tileset_path, &layout)?;
+
+        let autotile = autotile_rules(&base_path, &project)?;
+        let level_data = cook::load_level(&base_path, &base_path.join(format!("levels/{}.json", LEVEL)), tiles_per_row, &autotile)?;
+        let definitions = Definitions {
+            enemies: Archetypes::load(&base_path.join("enemies.json"))?,
+            platforms: PlatformKinds::load(&base_path.join("platforms.json"))?,
+            collectibles: CollectibleKinds::load(&base_path.join("collectibles.json"))?,
+            tiles: TilesetProperties::load(&base_path.join("tiles.json"))?
+                .get(&project.tilesets.first().unwrap().label)
+                .cloned()
+                .unwrap_or_default()
+        };
+        let world = World::new(&level_data, &definitions)?;
 
-        let vertices = vec![
-            Vertex { position: glam::vec2(0.0, 0.0) * glam::vec2(pt_data.width() as f32, pt_data.height() as f32), tex_coords: glam::vec2(0.0, 1.0)},
-            Vertex { position: glam::vec2(1.0, 0.0) * glam::vec2(pt_data.width() as f32, pt_data.height() as f32), tex_coords: glam::vec2(1.0, 1.0)},
-            Vertex { position: glam::vec2(1.0, 1.0) * glam::vec2(pt_data.width() as f32, pt_data.height() as f32), tex_coords: glam::vec2(1.0, 0.0)},
-            Vertex { position: glam::vec2(0.0, 1.0) * glam::vec2(pt_data.width() as f32, pt_data.height() as f32), tex_coords: glam::vec2(0.0, 0.0)},
-        ];
-        let indices : Vec<u16> = vec![0, 1, 2, 0, 2, 3];
-
-        let vertex_buffer = display.device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("Vertex Buffer"),
-            contents: bytemuck::cast_slice(&vertices),
-            usage: wgpu::BufferUsage::VERTEX,
-        });
+        let tilemap = ChunkedTilemap::new(display, &level_data.grid, &definitions.tiles.animations, tile_count,
+                                          &tileset_texture_view, camera_buffer.layout())?;
 
-        let index_buffer = display.device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("Index Buffer"),
-            contents: bytemuck::cast_slice(&indices),
-            usage: wgpu::BufferUsage::INDEX,
-        });
-        let num_indices = indices.len() as u32;
+        let backgrounds = LevelBackgrounds::load(&base_path.join("backgrounds.json"))?
+            .get(LEVEL)
+            .cloned()
+            .unwrap_or_default();
+        let background = ParallaxBackground::new(display, &base_path, &backgrounds, uvec2(layout.tile_width, layout.tile_height),
+                                                 level_data.grid.size(), camera_buffer.layout())?;
 
+        let sprite_renderer = SpriteRenderer::new(display, &tileset_texture_view, camera_buffer.layout());
+        let sprite_batch = SpriteBatch::new(&display.device, 64);
+        // Loose sprites of the hud are packed into an atlas at startup
+        let atlas = AtlasBuilder::default().build(&base_path.join("sprites"))?;
+        let atlas_texture = atlas.pages.to_texture(&display.device, &display.queue, wgpu::TextureFormat::Rgba8UnormSrgb,
+                                                   wgpu::TextureUsage::SAMPLED | wgpu::TextureUsage::COPY_DST)?;
+        let hud_renderer = SpriteRenderer::new(display, &atlas.pages.create_view(&atlas_texture), camera_buffer.layout());
+        let hud = Hud::new(display, &atlas)?;
 
 
         Ok(Self {
-            render_pipeline,
-            vertex_buffer,
-            index_buffer,
-            num_indices,
+            background,
+            tilemap,
+            debug_grid: false,
+            pixel_art: false,
             camera,
             camera_buffer,
-            diffuse_bind_group
+            sprite_renderer,
+            sprite_batch,
+            hud_renderer,
+            world,
+            hud,
+            camera_follow: true,
+            save,
+            save_path,
+            next_level
         })
     }
 
     #[allow(unused_variables)]
     fn resize(&mut self, display: &Display, width: u32, height: u32) {
         self.camera.calc_aspect(width, height);
+        self.hud.resize(width, height);
     }
 
     #[allow(unused_variables)]
     fn update(&mut self, display: &Display, dt: Duration) {
+        let dt = dt.as_secs_f32().min(MAX_TIME_STEP);
+        self.world.update(&display.input, dt);
+        self.tilemap.update(&display.queue, dt);
+
+        if let Some(completion) = self.world.take_completion() {
+            self.save.record_completion(LEVEL, completion.time, completion.collected, self.next_level.as_deref());
+            self.write_save(display);
+        }
 
+        if display.input.is_pressed(VirtualKeyCode::F3) {
+            self.save.settings.show_debug_ui = !self.save.settings.show_debug_ui;
+        }
+
+        if self.camera_follow {
+            let half_extent = vec2(self.camera.scale * self.camera.aspect, self.camera.scale);
+            let level_size = self.world.grid.size();
+            let target = self.world.player.body.center();
+            self.camera.position = vec2(
+                if level_size.x > 2.0 * half_extent.x { target.x.max(half_extent.x).min(level_size.x - half_extent.x) } else { level_size.x * 0.5 },
+                if level_size.y > 2.0 * half_extent.y { target.y.max(half_extent.y).min(level_size.y - half_extent.y) } else { level_size.y * 0.5 });
+        }
     }
 
     #[allow(unused_variables)]
     fn render(&mut self, display: &mut Display, encoder: &mut wgpu::CommandEncoder, frame: &wgpu::TextureView, ui: Option<&imgui::Ui>) {
 
-        if let Some(ui) = ui {
+        if let Some(ui) = ui.filter(|_| self.save.settings.show_debug_ui) {
             let window = imgui::Window::new(im_str!("Hello Imgui from WGPU!"));
             window
                 .size([300.0, 100.0], Condition::FirstUseEver)
@@ -271,12 +328,35 @@ impl Game for JumpAndRun {
                         1.0 / ui.io().delta_time
                     ));
                     ui.separator();
+                    ui.checkbox(im_str!("Follow Player"), &mut self.camera_follow);
+                    ui.text(im_str!(
+                        "Chunks: {}/{}",
+                        self.tilemap.visible_chunk_count(&self.camera),
+                        self.tilemap.chunk_count()
+                    ));
+                    ui.checkbox(im_str!("Debug Grid"), &mut self.debug_grid);
+                    ui.checkbox(im_str!("Pixel Art Filtering"), &mut self.pixel_art);
                     imgui::Drag::new(im_str!("Camera Position")).speed(0.1).build_array(&ui, self.camera.position.as_mut());
                     imgui::Drag::new(im_str!("Camera Scale")).speed(0.1).range(0.1..).build(&ui, &mut self.camera.scale);
                 });
         }
 
+        let mut permutation = 0;
+        if self.debug_grid {
+            permutation |= shaders::shader_frag::DEBUG_GRID;
+        }
+        if self.pixel_art {
+            permutation |= shaders::shader_frag::PIXEL_ART;
+        }
+        self.tilemap.set_permutation(display, self.camera_buffer.layout(), permutation);
+
         display.queue.update_uniform_buffer(&self.camera_buffer, &self.camera.to_matrix());
+        self.background.prepare(&display.queue, &self.camera);
+
+        self.sprite_batch.clear();
+        self.world.sprites(&mut self.sprite_batch);
+        self.sprite_batch.upload(&display.device, &display.queue);
+        self.hud.prepare(display, &self.world);
 
         let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
             label: Some("Render Pass"),
@@ -296,20 +376,28 @@ impl Game for JumpAndRun {
             depth_stencil_attachment: None,
         });
 
-        render_pass.set_pipeline(&self.render_pipeline);
-        render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
-        render_pass.set_index_buffer(self.index_buffer.slice(..), wgpu::IndexFormat::Uint16);
-        render_pass.set_bind_group(0, &self.diffuse_bind_group, &[]);
-        render_pass.set_uniform_buffer(1, &self.camera_buffer);
-        render_pass.draw_indexed(0..self.num_indices, 0, 0..1);
+        self.background.render(&mut render_pass, &self.camera_buffer);
+        self.tilemap.render(&mut render_pass, &self.camera, &self.camera_buffer);
+
+        self.sprite_renderer.render(&mut render_pass, &self.sprite_batch, &self.camera_buffer);
+        self.hud.render(&self.hud_renderer, &mut render_pass);
 
         //Ok(())
     }
+
+    fn exit(&mut self, display: &Display) {
+        self.write_save(display);
+    }
 }
 
 fn main() -> Result<()> {
     use futures::executor::block_on;
 
+    if std::env::args().any(|arg| arg == "--cook") {
+        env_logger::init();
+        return cook_assets();
+    }
+
     block_on(run::<JumpAndRun>())?;
 
     Ok(())
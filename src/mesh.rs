@@ -0,0 +1,213 @@
+use std::marker::PhantomData;
+use wgpu::util::DeviceExt;
+
+/// A `#[repr(C)]` struct that is read by the vertex shader, usually implemented through `impl_vertex!`.
+pub trait Vertex: bytemuck::Pod {
+    const ATTRIBUTES: &'static [wgpu::VertexAttribute];
+
+    fn layout(step_mode: wgpu::InputStepMode) -> wgpu::VertexBufferLayout<'static> {
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<Self>() as wgpu::BufferAddress,
+            step_mode,
+            attributes: Self::ATTRIBUTES,
+        }
+    }
+}
+
+#[doc(hidden)]
+pub const fn number_locations<const N: usize>(mut attributes: [wgpu::VertexAttribute; N]) -> [wgpu::VertexAttribute; N] {
+    let mut i = 0;
+    while i < N {
+        attributes[i].shader_location = i as wgpu::ShaderLocation;
+        i += 1;
+    }
+    attributes
+}
+
+/// Implements `Vertex` for a struct. Every field becomes an attribute at the next shader location,
+/// starting at 0, with the offset taken from the struct itself:
+/// `impl_vertex!(Vertex { position: Float2, tex_coords: Float2 });`
+#[macro_export]
+macro_rules! impl_vertex {
+    ($ty:ty { $($field:ident : $format:ident),* $(,)? }) => {
+        impl $crate::mesh::Vertex for $ty {
+            const ATTRIBUTES: &'static [wgpu::VertexAttribute] = &$crate::mesh::number_locations([$(
+                wgpu::VertexAttribute {
+                    format: wgpu::VertexFormat::$format,
+                    offset: std::mem::offset_of!($ty, $field) as wgpu::BufferAddress,
+                    shader_location: 0,
+                }
+            ),*]);
+        }
+    }
+}
+
+/// `u16` or `u32` indices.
+pub trait Index: bytemuck::Pod {
+    const FORMAT: wgpu::IndexFormat;
+}
+
+impl Index for u16 {
+    const FORMAT: wgpu::IndexFormat = wgpu::IndexFormat::Uint16;
+}
+
+impl Index for u32 {
+    const FORMAT: wgpu::IndexFormat = wgpu::IndexFormat::Uint32;
+}
+
+/// A gpu buffer of `T` that remembers how many elements it holds and grows when more are written.
+struct TypedBuffer<T> where T: bytemuck::Pod {
+    buffer: wgpu::Buffer,
+    usage: wgpu::BufferUsage,
+    label: &'static str,
+    capacity: usize,
+    len: u32,
+    _marker: PhantomData<T>
+}
+
+impl<T> TypedBuffer<T> where T: bytemuck::Pod {
+
+    fn new(device: &wgpu::Device, label: &'static str, usage: wgpu::BufferUsage, capacity: usize) -> Self {
+        let usage = usage | wgpu::BufferUsage::COPY_DST;
+        Self {
+            buffer: Self::create_buffer(device, label, usage, capacity),
+            usage,
+            label,
+            capacity,
+            len: 0,
+            _marker: PhantomData {}
+        }
+    }
+
+    fn with_data(device: &wgpu::Device, label: &'static str, usage: wgpu::BufferUsage, data: &[T]) -> Self {
+        let usage = usage | wgpu::BufferUsage::COPY_DST;
+        Self {
+            buffer: device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some(label),
+                contents: &Self::padded_bytes(data),
+                usage,
+            }),
+            usage,
+            label,
+            capacity: data.len(),
+            len: data.len() as u32,
+            _marker: PhantomData {}
+        }
+    }
+
+    /// Buffer sizes and writes have to be multiples of four bytes.
+    fn byte_size(len: usize) -> wgpu::BufferAddress {
+        ((len.max(1) * std::mem::size_of::<T>() + 3) & !3) as wgpu::BufferAddress
+    }
+
+    fn padded_bytes(data: &[T]) -> Vec<u8> {
+        let mut bytes = bytemuck::cast_slice(data).to_vec();
+        bytes.resize(Self::byte_size(data.len()) as usize, 0);
+        bytes
+    }
+
+    fn create_buffer(device: &wgpu::Device, label: &str, usage: wgpu::BufferUsage, capacity: usize) -> wgpu::Buffer {
+        device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some(label),
+            size: Self::byte_size(capacity),
+            usage,
+            mapped_at_creation: false
+        })
+    }
+
+    fn write(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, data: &[T]) {
+        if data.len() > self.capacity {
+            self.capacity = data.len().next_power_of_two();
+            self.buffer = Self::create_buffer(device, self.label, self.usage, self.capacity);
+        }
+        if !data.is_empty() {
+            queue.write_buffer(&self.buffer, 0, &Self::padded_bytes(data));
+        }
+        self.len = data.len() as u32;
+    }
+
+    fn slice(&self) -> wgpu::BufferSlice {
+        self.buffer.slice(..(self.len as usize * std::mem::size_of::<T>()) as wgpu::BufferAddress)
+    }
+
+}
+
+pub struct VertexBuffer<T> where T: Vertex {
+    buffer: TypedBuffer<T>
+}
+
+impl<T> VertexBuffer<T> where T: Vertex {
+
+    pub fn new(device: &wgpu::Device, label: &'static str, capacity: usize) -> Self {
+        Self { buffer: TypedBuffer::new(device, label, wgpu::BufferUsage::VERTEX, capacity) }
+    }
+
+    pub fn with_data(device: &wgpu::Device, label: &'static str, vertices: &[T]) -> Self {
+        Self { buffer: TypedBuffer::with_data(device, label, wgpu::BufferUsage::VERTEX, vertices) }
+    }
+
+    pub fn len(&self) -> u32 {
+        self.buffer.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.buffer.len == 0
+    }
+
+    /// Replaces the contents, growing the buffer if needed.
+    pub fn write(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, vertices: &[T]) {
+        self.buffer.write(device, queue, vertices);
+    }
+
+    pub fn slice(&self) -> wgpu::BufferSlice {
+        self.buffer.slice()
+    }
+
+}
+
+pub struct IndexBuffer<I> where I: Index {
+    buffer: TypedBuffer<I>
+}
+
+impl<I> IndexBuffer<I> where I: Index {
+
+    pub fn with_data(device: &wgpu::Device, label: &'static str, indices: &[I]) -> Self {
+        Self { buffer: TypedBuffer::with_data(device, label, wgpu::BufferUsage::INDEX, indices) }
+    }
+
+    pub fn len(&self) -> u32 {
+        self.buffer.len
+    }
+
+    pub fn slice(&self) -> wgpu::BufferSlice {
+        self.buffer.slice()
+    }
+
+}
+
+/// Indexed triangles.
+pub struct Mesh<V, I> where V: Vertex, I: Index {
+    pub vertices: VertexBuffer<V>,
+    pub indices: IndexBuffer<I>
+}
+
+impl<V, I> Mesh<V, I> where V: Vertex, I: Index {
+
+    pub fn new(device: &wgpu::Device, label: &'static str, vertices: &[V], indices: &[I]) -> Self {
+        Self {
+            vertices: VertexBuffer::with_data(device, label, vertices),
+            indices: IndexBuffer::with_data(device, label, indices)
+        }
+    }
+
+    /// Binds the vertices to slot 0.
+    pub fn draw<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>) {
+        if self.indices.len() == 0 {
+            return;
+        }
+        render_pass.set_vertex_buffer(0, self.vertices.slice());
+        render_pass.set_index_buffer(self.indices.slice(), I::FORMAT);
+        render_pass.draw_indexed(0..self.indices.len(), 0, 0..1);
+    }
+
+}
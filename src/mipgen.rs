@@ -0,0 +1,186 @@
+use std::num::NonZeroU32;
+use crate::include_wgsl_out;
+use crate::pipeline::PipelineBuilder;
+use crate::bindings::BindGroupLayout;
+
+/// Fills the mip chain of every array layer on the gpu by repeatedly rendering each level into the next one.
+/// Each level is a box filtered copy of the previous one, the same as the cpu path with `MipFilter::Box`.
+/// The texture needs the `SAMPLED` and `RENDER_ATTACHMENT` usages and the format the generator was created for.
+pub struct MipGenerator {
+    format: wgpu::TextureFormat,
+    render_pipeline: wgpu::RenderPipeline,
+    bind_group_layout: BindGroupLayout
+}
+
+impl MipGenerator {
+
+    pub fn new(device: &wgpu::Device, format: wgpu::TextureFormat) -> Self {
+        // Both entry points are in the same module
+        let shader = include_wgsl_out!("blit.wgsl");
+
+        // Wgsl bindings aren't reflected, this has to match `t_source` in blit.wgsl
+        let bind_group_layout = BindGroupLayout::new(device, "mip_bind_group_layout", &[
+            wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStage::FRAGMENT,
+                ty: wgpu::BindingType::Texture {
+                    sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                    multisampled: false,
+                },
+                count: None,
+            }
+        ]);
+
+        let render_pipeline = PipelineBuilder::new("Mip Pipeline", &shader, &shader, format)
+            .bind_group(&bind_group_layout)
+            .build(device);
+
+        Self {
+            format,
+            render_pipeline,
+            bind_group_layout
+        }
+    }
+
+    pub fn format(&self) -> wgpu::TextureFormat {
+        self.format
+    }
+
+    /// Records a render pass per layer and level that derives the level from the one above it.
+    pub fn generate(&self, device: &wgpu::Device, encoder: &mut wgpu::CommandEncoder, texture: &wgpu::Texture, layers: u32, mipmaps: u32) {
+        for layer in 0..layers {
+            let views = (0..mipmaps).map(|mipmap| texture.create_view(&wgpu::TextureViewDescriptor {
+                label: Some("mip_view"),
+                format: Some(self.format),
+                dimension: Some(wgpu::TextureViewDimension::D2),
+                aspect: wgpu::TextureAspect::All,
+                base_mip_level: mipmap,
+                level_count: NonZeroU32::new(1),
+                base_array_layer: layer,
+                array_layer_count: NonZeroU32::new(1),
+            })).collect::<Vec<_>>();
+
+            for mipmap in 1..mipmaps as usize {
+                let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+                    layout: &self.bind_group_layout,
+                    entries: &[
+                        wgpu::BindGroupEntry {
+                            binding: 0,
+                            resource: wgpu::BindingResource::TextureView(&views[mipmap - 1]),
+                        },
+                    ],
+                    label: Some("mip_bind_group"),
+                });
+
+                let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                    label: Some("Mip Pass"),
+                    color_attachments: &[wgpu::RenderPassColorAttachmentDescriptor {
+                        attachment: &views[mipmap],
+                        resolve_target: None,
+                        ops: wgpu::Operations {
+                            load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                            store: true,
+                        },
+                    }],
+                    depth_stencil_attachment: None,
+                });
+                render_pass.set_pipeline(&self.render_pipeline);
+                render_pass.set_bind_group(0, &bind_group, &[]);
+                render_pass.draw(0..3, 0..1);
+            }
+        }
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::executor::block_on;
+    use crate::texture::{TextureData, MipMaps};
+    use crate::mipmap::MipFilter;
+    use crate::framework::tests::device;
+    use super::*;
+
+    /// The gpu stores every level as 8 bit srgb before deriving the next one while the cpu keeps them unquantized,
+    /// so the rounding of up to five levels adds up to a few steps.
+    const TOLERANCE: u8 = 4;
+
+    /// Copies one level of the first layer back into tightly packed pixels.
+    fn read_level(device: &wgpu::Device, queue: &wgpu::Queue, texture: &wgpu::Texture, mipmap: u32, width: u32, height: u32) -> Vec<[u8; 4]> {
+        let align = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+        let bytes_per_row = (width * 4 + align - 1) / align * align;
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("mip_readback_buffer"),
+            size: (bytes_per_row * height) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsage::COPY_DST | wgpu::BufferUsage::MAP_READ,
+            mapped_at_creation: false,
+        });
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: Some("Readback Encoder") });
+        encoder.copy_texture_to_buffer(
+            wgpu::TextureCopyView {
+                texture,
+                mip_level: mipmap,
+                origin: wgpu::Origin3d::ZERO
+            },
+            wgpu::BufferCopyView {
+                buffer: &buffer,
+                layout: wgpu::TextureDataLayout {
+                    offset: 0,
+                    bytes_per_row,
+                    rows_per_image: height
+                }
+            },
+            wgpu::Extent3d { width, height, depth: 1 });
+        queue.submit(std::iter::once(encoder.finish()));
+
+        let slice = buffer.slice(..);
+        let mapping = slice.map_async(wgpu::MapMode::Read);
+        device.poll(wgpu::Maintain::Wait);
+        block_on(mapping).unwrap();
+        let data = slice.get_mapped_range();
+        data.chunks(bytes_per_row as usize)
+            .flat_map(|row| bytemuck::cast_slice::<u8, [u8; 4]>(&row[..(width * 4) as usize]).to_vec())
+            .collect()
+    }
+
+    #[test]
+    fn matches_cpu_box_filter() {
+        let (device, queue) = match device() {
+            Some(device) => device,
+            None => {
+                eprintln!("No adapter, skipping the gpu mipmap comparison");
+                return;
+            }
+        };
+
+        // Odd sizes and translucent texels are where a single bilinear tap would differ
+        let (width, height) = (37, 21);
+        let mut td = TextureData::<[u8; 4]>::new(width, height, 1, MipMaps::All);
+        for y in 0..height {
+            for x in 0..width {
+                *td.get_pixel_mut(x, y, 0, 0) = [
+                    (x * 255 / (width - 1)) as u8,
+                    (y * 255 / (height - 1)) as u8,
+                    ((x + y) * 7 % 256) as u8,
+                    (64 + (x * 3 + y * 5) % 192) as u8
+                ];
+            }
+        }
+
+        let format = wgpu::TextureFormat::Rgba8UnormSrgb;
+        let generator = MipGenerator::new(&device, format);
+        let texture = td.to_texture_with_gpu_mipmaps(&device, &queue, wgpu::TextureUsage::COPY_SRC, &generator).unwrap();
+        td.generate_mipmaps(MipFilter::Box);
+
+        for mipmap in 1..td.mipmaps() {
+            let (level_width, level_height) = (td.mipmapped_width(mipmap), td.mipmapped_height(mipmap));
+            let gpu = read_level(&device, &queue, &texture, mipmap, level_width, level_height);
+            for (i, (gpu, cpu)) in gpu.iter().zip(td.get_mipmap(0, mipmap)).enumerate() {
+                let difference = gpu.iter().zip(cpu).map(|(a, b)| (*a as i16 - *b as i16).abs() as u8).max().unwrap();
+                assert!(difference <= TOLERANCE, "Level {} texel {} is {:?} on the gpu and {:?} on the cpu", mipmap, i, gpu, cpu);
+            }
+        }
+    }
+
+}
@@ -0,0 +1,272 @@
+use std::f32::consts::PI;
+use crate::pixel::Pixel;
+
+/// The reconstruction filter used to downsample one mip level into the next.
+#[allow(dead_code)]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum MipFilter {
+    /// Averages exactly the footprint of the destination pixel. Cheap and never rings.
+    Box,
+    /// Kaiser windowed sinc. Sharper than the box filter with very little ringing.
+    Kaiser,
+    /// Lanczos windowed sinc with three lobes. The sharpest, but may ring at hard edges.
+    Lanczos
+}
+
+impl Default for MipFilter {
+    fn default() -> Self {
+        MipFilter::Box
+    }
+}
+
+const KAISER_ALPHA: f32 = 4.0;
+
+impl MipFilter {
+
+    /// Radius of the filter in destination pixels.
+    fn support(self) -> f32 {
+        match self {
+            MipFilter::Box => 0.5,
+            MipFilter::Kaiser => 3.0,
+            MipFilter::Lanczos => 3.0
+        }
+    }
+
+    fn evaluate(self, x: f32) -> f32 {
+        let support = self.support();
+        if x.abs() >= support {
+            return 0.0;
+        }
+        match self {
+            MipFilter::Box => 1.0,
+            MipFilter::Kaiser => {
+                let t = x / support;
+                sinc(x) * bessel_i0(KAISER_ALPHA * f32::sqrt(1.0 - t * t)) / bessel_i0(KAISER_ALPHA)
+            }
+            MipFilter::Lanczos => sinc(x) * sinc(x / support)
+        }
+    }
+
+    /// The normalized source taps of every destination pixel when resampling `src` pixels to `dst` pixels.
+    /// Taps outside the source are clamped to the edge.
+    fn weights(self, src: u32, dst: u32) -> Vec<Vec<(usize, f32)>> {
+        let scale = src as f32 / dst as f32;
+        (0..dst).map(|i| {
+            let center = (i as f32 + 0.5) * scale;
+            let radius = self.support() * scale;
+            let first = f32::floor(center - radius) as i32;
+            let last = f32::ceil(center + radius) as i32;
+            let mut taps: Vec<(usize, f32)> = Vec::new();
+            for j in first..last {
+                let weight = match self {
+                    // The box filter uses the exact overlap, so odd sizes blend the shared pixel proportionally
+                    MipFilter::Box => f32::max(0.0, f32::min(j as f32 + 1.0, center + radius) - f32::max(j as f32, center - radius)),
+                    _ => self.evaluate((j as f32 + 0.5 - center) / scale)
+                };
+                if weight == 0.0 {
+                    continue;
+                }
+                let index = j.clamp(0, src as i32 - 1) as usize;
+                match taps.iter_mut().find(|(k, _)| *k == index) {
+                    Some((_, w)) => *w += weight,
+                    None => taps.push((index, weight))
+                }
+            }
+            let sum: f32 = taps.iter().map(|(_, w)| w).sum();
+            taps.iter_mut().for_each(|(_, w)| *w /= sum);
+            taps
+        }).collect()
+    }
+
+}
+
+fn sinc(x: f32) -> f32 {
+    if x.abs() < 1e-6 {
+        1.0
+    } else {
+        f32::sin(PI * x) / (PI * x)
+    }
+}
+
+/// Zeroth order modified bessel function of the first kind, used by the Kaiser window.
+fn bessel_i0(x: f32) -> f32 {
+    let mut sum = 1.0;
+    let mut term = 1.0;
+    let mut k = 1.0;
+    while term > sum * 1e-7 {
+        term *= (x * 0.5 / k) * (x * 0.5 / k);
+        sum += term;
+        k += 1.0;
+    }
+    sum
+}
+
+pub fn srgb_to_linear(value: f32) -> f32 {
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        f32::powf((value + 0.055) / 1.055, 2.4)
+    }
+}
+
+pub fn linear_to_srgb(value: f32) -> f32 {
+    if value <= 0.0031308 {
+        value * 12.92
+    } else {
+        1.055 * f32::powf(value, 1.0 / 2.4) - 0.055
+    }
+}
+
+/// A single mip level in linear space with premultiplied alpha.
+#[derive(Clone)]
+pub struct LinearImage {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<[f32; 4]>
+}
+
+impl LinearImage {
+
+    /// Formats with alpha get premultiplied.
+    pub fn from_pixels<P: Pixel>(width: u32, height: u32, pixels: &[P]) -> Self {
+        let pixels = pixels.iter().map(|p| {
+            let mut linear = p.to_linear();
+            if P::HAS_ALPHA {
+                for c in 0..3 {
+                    linear[c] *= linear[3];
+                }
+            }
+            linear
+        }).collect();
+        Self { width, height, pixels }
+    }
+
+    pub fn to_pixels<P: Pixel>(&self) -> Vec<P> {
+        self.pixels.iter().map(|p| {
+            let mut linear = *p;
+            if P::HAS_ALPHA {
+                let alpha = p[3].max(0.0);
+                for c in 0..3 {
+                    linear[c] = if alpha > 0.0 { p[c] / alpha } else { 0.0 };
+                }
+                linear[3] = alpha;
+            }
+            P::from_linear(linear)
+        }).collect()
+    }
+
+    /// Separable resampling, first along x and then along y.
+    pub fn resample(&self, width: u32, height: u32, filter: MipFilter) -> Self {
+        let horizontal = filter.weights(self.width, width);
+        let mut temp = vec![[0.0f32; 4]; (width * self.height) as usize];
+        for y in 0..self.height as usize {
+            let row = &self.pixels[y * self.width as usize..(y + 1) * self.width as usize];
+            for (x, taps) in horizontal.iter().enumerate() {
+                temp[x + y * width as usize] = accumulate(taps.iter().map(|&(i, w)| (&row[i], w)));
+            }
+        }
+        let vertical = filter.weights(self.height, height);
+        let mut pixels = vec![[0.0f32; 4]; (width * height) as usize];
+        for (y, taps) in vertical.iter().enumerate() {
+            for x in 0..width as usize {
+                pixels[x + y * width as usize] = accumulate(taps.iter().map(|&(i, w)| (&temp[x + i * width as usize], w)));
+            }
+        }
+        Self { width, height, pixels }
+    }
+
+}
+
+/// Resamples a stack of equally sized slices to `depth` slices, for the mip levels of 3d textures.
+pub fn resample_depth(slices: &[LinearImage], depth: u32, filter: MipFilter) -> Vec<LinearImage> {
+    if slices.len() as u32 == depth {
+        return slices.to_vec();
+    }
+    filter.weights(slices.len() as u32, depth).iter().map(|taps| {
+        let (width, height) = (slices[0].width, slices[0].height);
+        let pixels = (0..(width * height) as usize)
+            .map(|i| accumulate(taps.iter().map(|&(z, w)| (&slices[z].pixels[i], w))))
+            .collect();
+        LinearImage { width, height, pixels }
+    }).collect()
+}
+
+fn accumulate<'a>(taps: impl Iterator<Item=(&'a [f32; 4], f32)>) -> [f32; 4] {
+    let mut result = [0.0; 4];
+    for (pixel, weight) in taps {
+        for c in 0..4 {
+            result[c] += pixel[c] * weight;
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FILTERS: [MipFilter; 3] = [MipFilter::Box, MipFilter::Kaiser, MipFilter::Lanczos];
+
+    #[test]
+    fn weights_sum_to_one() {
+        for &filter in &FILTERS {
+            for &(src, dst) in &[(64, 32), (37, 18), (21, 10), (5, 2), (3, 1), (100, 7), (1, 1)] {
+                for taps in filter.weights(src, dst) {
+                    let sum: f32 = taps.iter().map(|(_, w)| w).sum();
+                    assert!((sum - 1.0).abs() < 1e-5, "{:?} {} -> {} sums to {}", filter, src, dst, sum);
+                    assert!(taps.iter().all(|&(i, _)| i < src as usize));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn box_filter_blends_odd_sizes_proportionally() {
+        let weights = MipFilter::Box.weights(3, 1);
+        assert_eq!(weights.len(), 1);
+        assert_eq!(weights[0].len(), 3);
+        for &(_, w) in &weights[0] {
+            assert!((w - 1.0 / 3.0).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn resampling_keeps_constant_images() {
+        let pixels = vec![[0.25, 0.5, 0.75, 1.0]; 37 * 21];
+        let image = LinearImage { width: 37, height: 21, pixels };
+        for &filter in &FILTERS {
+            let mip = image.resample(18, 10, filter);
+            assert_eq!((mip.width, mip.height, mip.pixels.len()), (18, 10, 180));
+            for pixel in &mip.pixels {
+                for c in 0..4 {
+                    assert!((pixel[c] - image.pixels[0][c]).abs() < 1e-5, "{:?} gave {:?}", filter, pixel);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn srgb_round_trips_every_byte() {
+        for value in 0..=255u8 {
+            let srgb = linear_to_srgb(srgb_to_linear(value as f32 / 255.0));
+            assert_eq!((srgb * 255.0).round() as u8, value);
+        }
+    }
+
+    #[test]
+    fn premultiplies_alpha() {
+        let image = LinearImage::from_pixels(2, 1, &[[255u8, 0, 0, 128], [0, 255, 0, 0]]);
+        let alpha = 128.0 / 255.0;
+        assert_eq!(image.pixels[0], [alpha, 0.0, 0.0, alpha]);
+        assert_eq!(image.pixels[1], [0.0; 4]);
+        assert_eq!(image.to_pixels::<[u8; 4]>(), vec![[255, 0, 0, 128], [0, 0, 0, 0]]);
+    }
+
+    #[test]
+    fn transparent_pixels_dont_bleed_into_the_mip() {
+        let image = LinearImage::from_pixels(2, 1, &[[255u8, 0, 0, 255], [0, 255, 0, 0]]);
+        let mip = image.resample(1, 1, MipFilter::Box);
+        assert_eq!(mip.to_pixels::<[u8; 4]>(), vec![[255, 0, 0, 128]]);
+    }
+
+}
@@ -0,0 +1,28 @@
+#version 450
+
+layout(location=0) in vec2 v_position;
+
+layout(location=0) out vec4 f_color;
+
+layout(set = 0, binding = 0) uniform texture2D t_background;
+layout(set = 0, binding = 1) uniform sampler s_background;
+
+layout(set=2, binding=0)
+uniform Layer {
+    vec2 view_min;
+    vec2 view_max;
+    vec2 offset;
+    vec2 size;
+    uvec2 repeat_axes;
+};
+
+void main() {
+    vec2 uv = (v_position - offset) / size;
+    // Axes that don't repeat are clamped by the sampler and cut off here
+    bvec2 outside = bvec2(uv.x < 0.0 || uv.x > 1.0, uv.y < 0.0 || uv.y > 1.0);
+    if((outside.x && repeat_axes.x == 0u) || (outside.y && repeat_axes.y == 0u)) {
+        discard;
+    }
+    // Image rows go down, the world y axis up
+    f_color = texture(sampler2D(t_background, s_background), vec2(uv.x, 1.0 - uv.y));
+}
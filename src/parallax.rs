@@ -0,0 +1,217 @@
+use std::path::Path;
+use std::rc::Rc;
+use anyhow::*;
+use glam::*;
+use serde::Deserialize;
+use crate::framework::Display;
+use crate::buffer::{UniformBuffer, UniformArrayBuffer, BufferOptions, BindUniformBuffer, BindUniformArrayBuffer};
+use crate::texture::TextureData;
+use crate::catalog::Catalog;
+use crate::camera::Camera;
+use crate::cook::TILESET_FILTER;
+use crate::pipeline::{PipelineBuilder, BlendMode};
+use crate::bindings::{self, BindGroupLayout};
+use crate::{include_spirv_out, assert_std140, assert_buffer_set};
+
+/// The axes along which a layer image is tiled.
+#[derive(Deserialize, Copy, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Repeat {
+    None,
+    X,
+    Y,
+    Both
+}
+
+impl Default for Repeat {
+    fn default() -> Self {
+        Repeat::X
+    }
+}
+
+impl Repeat {
+
+    /// Axes that don't repeat are clamped, so linear filtering doesn't blend in the opposite edge.
+    fn address_modes(self) -> (wgpu::AddressMode, wgpu::AddressMode) {
+        let mode = |repeats| if repeats { wgpu::AddressMode::Repeat } else { wgpu::AddressMode::ClampToEdge };
+        (mode(matches!(self, Repeat::X | Repeat::Both)), mode(matches!(self, Repeat::Y | Repeat::Both)))
+    }
+
+}
+
+/// Where a layer sits vertically in the level while the camera is at the level center.
+#[derive(Deserialize, Copy, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum VerticalAnchor {
+    Bottom,
+    Center,
+    Top
+}
+
+impl Default for VerticalAnchor {
+    fn default() -> Self {
+        VerticalAnchor::Bottom
+    }
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct ParallaxLayer {
+    /// Relative to the assets folder.
+    pub image: String,
+    /// How much the layer moves with the camera per axis, 0 stays fixed on screen and 1 moves with the tiles.
+    pub scroll: [f32; 2],
+    #[serde(default)]
+    pub repeat: Repeat,
+    #[serde(default)]
+    pub anchor: VerticalAnchor,
+    /// How the layer is drawn over the layers behind it, e.g. `additive` for glows and light shafts.
+    #[serde(default = "default_layer_blend")]
+    pub blend: BlendMode
+}
+
+fn default_layer_blend() -> BlendMode {
+    BlendMode::Alpha
+}
+
+/// Background layers of every level, back to front, keyed by the level name.
+pub type LevelBackgrounds = Catalog<Vec<ParallaxLayer>>;
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct LayerUniforms {
+    view_min: Vec2,
+    view_max: Vec2,
+    /// World position of the bottom left corner of the image.
+    offset: Vec2,
+    size: Vec2,
+    repeat_axes: UVec2,
+    _padding: Vec2
+}
+
+assert_std140!(LayerUniforms { view_min: Vec2, view_max: Vec2, offset: Vec2, size: Vec2, repeat_axes: UVec2, _padding: Vec2 });
+assert_buffer_set!(parallax::set2, wgpu::BufferBindingType::Uniform);
+
+struct Layer {
+    config: ParallaxLayer,
+    /// Shared by all layers with the same blend mode.
+    render_pipeline: Rc<wgpu::RenderPipeline>,
+    /// In world units.
+    size: Vec2,
+    bind_group: wgpu::BindGroup
+}
+
+/// Images drawn behind the tilemap that scroll slower than the level to give it depth.
+pub struct ParallaxBackground {
+    layers: Vec<Layer>,
+    /// One element per layer.
+    uniforms: UniformArrayBuffer<LayerUniforms>,
+    level_size: Vec2
+}
+
+impl ParallaxBackground {
+
+    /// Images are scaled so one of their pixels is as large as a pixel of the tileset, `tile_size` pixels per world unit.
+    pub fn new(display: &Display, base_path: &Path, config: &[ParallaxLayer], tile_size: UVec2, level_size: Vec2, camera_layout: &BindGroupLayout) -> Result<Self, Error> {
+        let vertex_shader = include_spirv_out!("parallax.vert.spv");
+        let fragment_shader = include_spirv_out!("parallax.frag.spv");
+
+        let bind_group_layout = BindGroupLayout::new(&display.device, "parallax_bind_group_layout", bindings::parallax::set0::ENTRIES);
+        let options = BufferOptions::default()
+            .label("Parallax Layer Buffer")
+            .visibility(bindings::parallax::set2::ENTRIES[0].visibility);
+        let uniforms = UniformArrayBuffer::new(&display.device, config.len() as u32, options);
+
+        let layers = config.iter().map(|config| {
+            let td = TextureData::from_image(&base_path.join(&config.image), TILESET_FILTER)?;
+            let texture = td.to_texture(&display.device, &display.queue, wgpu::TextureFormat::Rgba8UnormSrgb, wgpu::TextureUsage::SAMPLED | wgpu::TextureUsage::COPY_DST)?;
+            let view = td.create_view(&texture);
+            let (address_mode_u, address_mode_v) = config.repeat.address_modes();
+            let sampler = display.device.create_sampler(&wgpu::SamplerDescriptor {
+                address_mode_u,
+                address_mode_v,
+                address_mode_w: wgpu::AddressMode::ClampToEdge,
+                mag_filter: wgpu::FilterMode::Linear,
+                min_filter: wgpu::FilterMode::Linear,
+                mipmap_filter: wgpu::FilterMode::Linear,
+                ..Default::default()
+            });
+            let bind_group = display.device.create_bind_group(&wgpu::BindGroupDescriptor {
+                layout: &bind_group_layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: bindings::parallax::set0::T_BACKGROUND,
+                        resource: wgpu::BindingResource::TextureView(&view),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: bindings::parallax::set0::S_BACKGROUND,
+                        resource: wgpu::BindingResource::Sampler(&sampler),
+                    },
+                ],
+                label: Some("parallax_bind_group"),
+            });
+            let render_pipeline = display.pipeline(
+                &PipelineBuilder::new("Parallax Pipeline", &vertex_shader, &fragment_shader, display.sc_desc.format)
+                    .bind_group(&bind_group_layout)
+                    .bind_group(camera_layout)
+                    .bind_group(uniforms.layout())
+                    .blend(config.blend)
+            );
+            Ok(Layer {
+                config: config.clone(),
+                render_pipeline,
+                size: uvec2(td.width(), td.height()).as_f32() / tile_size.as_f32(),
+                bind_group
+            })
+        }).collect::<Result<Vec<_>, Error>>()?;
+
+        Ok(Self {
+            layers,
+            uniforms,
+            level_size
+        })
+    }
+
+    /// Moves the layers for the current camera position.
+    pub fn prepare(&self, queue: &wgpu::Queue, camera: &Camera) {
+        if self.layers.is_empty() {
+            return;
+        }
+        let view = camera.bounds();
+        // Every layer is at its anchored position while the camera is at the level center
+        let camera_offset = camera.position - self.level_size * 0.5;
+        let uniforms = self.layers.iter().map(|layer| {
+            let scroll = Vec2::from(layer.config.scroll);
+            let anchor_y = match layer.config.anchor {
+                VerticalAnchor::Bottom => 0.0,
+                VerticalAnchor::Center => (self.level_size.y - layer.size.y) * 0.5,
+                VerticalAnchor::Top => self.level_size.y - layer.size.y
+            };
+            let repeat = layer.config.repeat;
+            LayerUniforms {
+                view_min: view.min,
+                view_max: view.max,
+                offset: vec2(0.0, anchor_y) + camera_offset * (Vec2::ONE - scroll),
+                size: layer.size,
+                repeat_axes: uvec2(
+                    matches!(repeat, Repeat::X | Repeat::Both) as u32,
+                    matches!(repeat, Repeat::Y | Repeat::Both) as u32),
+                _padding: Vec2::ZERO
+            }
+        }).collect::<Vec<_>>();
+        self.uniforms.write_all(queue, &uniforms);
+    }
+
+    pub fn render<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>, camera_buffer: &'a UniformBuffer<Mat4>) {
+        if self.layers.is_empty() {
+            return;
+        }
+        for (i, layer) in self.layers.iter().enumerate() {
+            render_pass.set_pipeline(&layer.render_pipeline);
+            render_pass.set_uniform_buffer(1, camera_buffer);
+            render_pass.set_bind_group(0, &layer.bind_group, &[]);
+            render_pass.set_uniform_array_buffer(2, &self.uniforms, i as u32);
+            render_pass.draw(0..6, 0..1);
+        }
+    }
+
+}
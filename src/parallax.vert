@@ -0,0 +1,28 @@
+#version 450
+
+layout(location=0) out vec2 v_position;
+
+layout(set=1, binding=0)
+uniform Uniforms {
+    mat4 cam;
+};
+
+layout(set=2, binding=0)
+uniform Layer {
+    vec2 view_min;
+    vec2 view_max;
+    vec2 offset;
+    vec2 size;
+    uvec2 repeat_axes;
+};
+
+const vec2 corners[6] = vec2[6](
+    vec2(0.0, 0.0), vec2(1.0, 0.0), vec2(1.0, 1.0),
+    vec2(0.0, 0.0), vec2(1.0, 1.0), vec2(0.0, 1.0)
+);
+
+// Covers the visible part of the world, the fragment shader decides where the layer is
+void main() {
+    v_position = mix(view_min, view_max, corners[gl_VertexIndex]);
+    gl_Position = cam * vec4(v_position, 0.0, 1.0);
+}
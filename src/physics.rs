@@ -0,0 +1,210 @@
+use glam::*;
+use crate::tilemap::TileGrid;
+
+const EPSILON: f32 = 0.001;
+const MAX_STEP: f32 = 0.25;
+
+pub const GRAVITY: f32 = -50.0;
+pub const MAX_FALL_SPEED: f32 = 25.0;
+
+#[derive(Copy, Clone, Debug)]
+pub struct Aabb {
+    pub min: Vec2,
+    pub max: Vec2
+}
+
+impl Aabb {
+
+    pub fn new(position: Vec2, size: Vec2) -> Self {
+        Self {
+            min: position,
+            max: position + size
+        }
+    }
+
+    pub fn center(&self) -> Vec2 {
+        (self.min + self.max) * 0.5
+    }
+
+    pub fn overlaps(&self, other: &Aabb) -> bool {
+        self.min.x < other.max.x && self.max.x > other.min.x &&
+        self.min.y < other.max.y && self.max.y > other.min.y
+    }
+
+    fn rows(&self) -> std::ops::RangeInclusive<i32> {
+        (self.min.y + EPSILON).floor() as i32..=(self.max.y - EPSILON).floor() as i32
+    }
+
+    fn columns(&self) -> std::ops::RangeInclusive<i32> {
+        (self.min.x + EPSILON).floor() as i32..=(self.max.x - EPSILON).floor() as i32
+    }
+
+}
+
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Contacts {
+    pub floor: bool,
+    pub ceiling: bool,
+    pub left: bool,
+    pub right: bool,
+    /// The moving solid the body is standing on, if any.
+    pub support: Option<Aabb>
+}
+
+impl Contacts {
+    pub fn wall(&self) -> bool {
+        self.left || self.right
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct Body {
+    pub position: Vec2,
+    pub size: Vec2,
+    pub velocity: Vec2,
+    pub contacts: Contacts
+}
+
+impl Body {
+
+    pub fn new(position: Vec2, size: Vec2) -> Self {
+        Self {
+            position,
+            size,
+            velocity: Vec2::ZERO,
+            contacts: Contacts::default()
+        }
+    }
+
+    /// Places the body so that its bottom center is at `point`.
+    pub fn standing_at(point: Vec2, size: Vec2) -> Self {
+        Self::new(point - vec2(size.x * 0.5, 0.0), size)
+    }
+
+    pub fn aabb(&self) -> Aabb {
+        Aabb::new(self.position, self.size)
+    }
+
+    pub fn center(&self) -> Vec2 {
+        self.position + self.size * 0.5
+    }
+
+    pub fn feet(&self) -> Vec2 {
+        self.position + vec2(self.size.x * 0.5, 0.0)
+    }
+
+    pub fn apply_gravity(&mut self, dt: f32) {
+        self.velocity.y = (self.velocity.y + GRAVITY * dt).max(-MAX_FALL_SPEED);
+    }
+
+    /// Moves the body by its velocity and resolves collisions with the solid tiles of `grid`.
+    /// Movement is split into steps of at most `MAX_STEP` tiles so fast bodies can't tunnel.
+    pub fn move_and_collide(&mut self, grid: &TileGrid, dt: f32) -> Contacts {
+        self.move_by(grid, self.velocity * dt)
+    }
+
+    pub fn move_by(&mut self, grid: &TileGrid, delta: Vec2) -> Contacts {
+        let mut contacts = Contacts::default();
+        let steps = (delta.abs().max_element() / MAX_STEP).ceil().max(1.0);
+        let step = delta / steps;
+        for _ in 0..steps as u32 {
+            if !contacts.wall() {
+                self.step_x(grid, step.x, &mut contacts);
+            }
+            if !(contacts.floor || contacts.ceiling) {
+                self.step_y(grid, step.y, &mut contacts);
+            }
+        }
+        if contacts.wall() {
+            self.velocity.x = 0.0;
+        }
+        if (contacts.floor && self.velocity.y < 0.0) || (contacts.ceiling && self.velocity.y > 0.0) {
+            self.velocity.y = 0.0;
+        }
+        if !contacts.floor && delta.y <= 0.0 {
+            contacts.floor = self.is_on_ground(grid);
+        }
+        self.contacts = contacts;
+        contacts
+    }
+
+    fn step_x(&mut self, grid: &TileGrid, dx: f32, contacts: &mut Contacts) {
+        if dx == 0.0 {
+            return;
+        }
+        let target = Aabb::new(self.position + vec2(dx, 0.0), self.size);
+        let column = if dx > 0.0 { (target.max.x - EPSILON).floor() as i32 } else { target.min.x.floor() as i32 };
+        if target.rows().any(|row| grid.is_solid(column, row)) {
+            if dx > 0.0 {
+                self.position.x = column as f32 - self.size.x;
+                contacts.right = true;
+            } else {
+                self.position.x = (column + 1) as f32;
+                contacts.left = true;
+            }
+        } else {
+            self.position.x = target.min.x;
+        }
+    }
+
+    fn step_y(&mut self, grid: &TileGrid, dy: f32, contacts: &mut Contacts) {
+        if dy == 0.0 {
+            return;
+        }
+        let target = Aabb::new(self.position + vec2(0.0, dy), self.size);
+        let row = if dy > 0.0 { (target.max.y - EPSILON).floor() as i32 } else { target.min.y.floor() as i32 };
+        if target.columns().any(|column| grid.is_solid(column, row)) {
+            if dy > 0.0 {
+                self.position.y = row as f32 - self.size.y;
+                contacts.ceiling = true;
+            } else {
+                self.position.y = (row + 1) as f32;
+                contacts.floor = true;
+            }
+        } else {
+            self.position.y = target.min.y;
+        }
+    }
+
+    pub fn is_on_ground(&self, grid: &TileGrid) -> bool {
+        let row = (self.position.y - EPSILON).floor() as i32;
+        (self.position.y - (row + 1) as f32).abs() < 2.0 * EPSILON &&
+            self.aabb().columns().any(|column| grid.is_solid(column, row))
+    }
+
+    /// Returns `true` if there is solid ground right in front of the body when walking in `direction`.
+    pub fn has_ground_ahead(&self, grid: &TileGrid, direction: f32) -> bool {
+        let x = if direction > 0.0 { self.position.x + self.size.x + EPSILON } else { self.position.x - EPSILON };
+        match self.contacts.support {
+            Some(support) => (support.min.x..support.max.x).contains(&x),
+            None => grid.is_solid_at(vec2(x, self.position.y - 0.5))
+        }
+    }
+
+    /// Returns `true` if the body rests on the top of `solid`.
+    pub fn is_standing_on(&self, solid: &Aabb) -> bool {
+        (self.position.y - solid.max.y).abs() < 2.0 * EPSILON &&
+            self.position.x < solid.max.x && self.position.x + self.size.x > solid.min.x
+    }
+
+    /// Treats the top of `solid` as a one way floor: if the bottom of the body crossed it
+    /// since it was at `previous_bottom`, the body is put back on top of it.
+    pub fn land_on(&mut self, solid: &Aabb, previous_bottom: f32) -> bool {
+        let crossed = previous_bottom >= solid.max.y - 2.0 * EPSILON && self.position.y < solid.max.y;
+        let overlapping = self.position.x < solid.max.x && self.position.x + self.size.x > solid.min.x;
+        if crossed && overlapping && self.velocity.y <= 0.0 {
+            self.position.y = solid.max.y;
+            self.velocity.y = 0.0;
+            self.stand_on(solid);
+            true
+        } else {
+            false
+        }
+    }
+
+    pub fn stand_on(&mut self, solid: &Aabb) {
+        self.contacts.floor = true;
+        self.contacts.support = Some(*solid);
+    }
+
+}
@@ -0,0 +1,277 @@
+use std::collections::HashMap;
+use std::rc::Rc;
+use wgpu::{BlendFactor, BlendOperation};
+use serde::Deserialize;
+use crate::mesh::Vertex;
+use crate::bindings::BindGroupLayout;
+
+/// How the fragment shader output is combined with the render target.
+#[derive(Deserialize, Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum BlendMode {
+    /// Overwrites the target.
+    Replace,
+    /// Straight alpha, the color is scaled by its alpha.
+    Alpha,
+    /// Adds the color scaled by its alpha, for glows and particles.
+    Additive,
+    /// The color was already multiplied by its alpha.
+    Premultiplied
+}
+
+impl Default for BlendMode {
+    fn default() -> Self {
+        BlendMode::Replace
+    }
+}
+
+impl BlendMode {
+
+    fn blend_state(src_factor: BlendFactor, dst_factor: BlendFactor) -> wgpu::BlendState {
+        wgpu::BlendState {
+            src_factor,
+            dst_factor,
+            operation: BlendOperation::Add,
+        }
+    }
+
+    pub fn color_blend(self) -> wgpu::BlendState {
+        match self {
+            BlendMode::Replace => wgpu::BlendState::REPLACE,
+            BlendMode::Alpha => Self::blend_state(BlendFactor::SrcAlpha, BlendFactor::OneMinusSrcAlpha),
+            BlendMode::Additive => Self::blend_state(BlendFactor::SrcAlpha, BlendFactor::One),
+            BlendMode::Premultiplied => Self::blend_state(BlendFactor::One, BlendFactor::OneMinusSrcAlpha),
+        }
+    }
+
+    pub fn alpha_blend(self) -> wgpu::BlendState {
+        match self {
+            BlendMode::Replace => wgpu::BlendState::REPLACE,
+            BlendMode::Alpha | BlendMode::Additive => Self::blend_state(BlendFactor::One, BlendFactor::One),
+            BlendMode::Premultiplied => Self::blend_state(BlendFactor::One, BlendFactor::OneMinusSrcAlpha),
+        }
+    }
+
+}
+
+/// Identifies a pipeline in the `PipelineCache`. The shaders are identified by the label of their descriptor,
+/// the path of the spirv or wgsl file, and the bind group layouts by their entries.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct PipelineKey {
+    pub label: &'static str,
+    pub vertex_shader: &'static str,
+    pub fragment_shader: &'static str,
+    /// Stride, step mode and attributes of every vertex buffer.
+    pub vertex_buffers: Vec<(wgpu::BufferAddress, wgpu::InputStepMode, &'static [wgpu::VertexAttribute])>,
+    pub bind_group_layouts: Vec<Vec<wgpu::BindGroupLayoutEntry>>,
+    pub format: wgpu::TextureFormat,
+    pub blend: BlendMode,
+    pub cull_mode: wgpu::CullMode,
+    pub permutation: u32
+}
+
+/// Everything that differs between our render pipelines. The rest is fixed: `main` entry points, triangle lists,
+/// counter clockwise front faces, a single color target and no depth or multisampling.
+/// The shader modules are only created when the pipeline is built.
+pub struct PipelineBuilder<'a> {
+    label: &'static str,
+    vertex_shader: &'a wgpu::ShaderModuleDescriptor<'static>,
+    fragment_shader: &'a wgpu::ShaderModuleDescriptor<'static>,
+    vertex_buffers: Vec<wgpu::VertexBufferLayout<'static>>,
+    bind_group_layouts: Vec<&'a BindGroupLayout>,
+    format: wgpu::TextureFormat,
+    blend: BlendMode,
+    cull_mode: wgpu::CullMode,
+    permutation: u32
+}
+
+impl<'a> PipelineBuilder<'a> {
+
+    /// The shaders come from `include_spirv_out!`, `include_wgsl_out!` or a `shaders` permutation.
+    pub fn new(label: &'static str, vertex_shader: &'a wgpu::ShaderModuleDescriptor<'static>, fragment_shader: &'a wgpu::ShaderModuleDescriptor<'static>,
+               format: wgpu::TextureFormat) -> Self {
+        Self {
+            label,
+            vertex_shader,
+            fragment_shader,
+            vertex_buffers: Vec::new(),
+            bind_group_layouts: Vec::new(),
+            format,
+            blend: BlendMode::default(),
+            cull_mode: wgpu::CullMode::None,
+            permutation: 0
+        }
+    }
+
+    /// Adds the next vertex buffer slot.
+    pub fn vertex<V: Vertex>(mut self, step_mode: wgpu::InputStepMode) -> Self {
+        self.vertex_buffers.push(V::layout(step_mode));
+        self
+    }
+
+    /// Adds the next bind group.
+    pub fn bind_group(mut self, layout: &'a BindGroupLayout) -> Self {
+        self.bind_group_layouts.push(layout);
+        self
+    }
+
+    pub fn blend(self, blend: BlendMode) -> Self {
+        Self { blend, ..self }
+    }
+
+    pub fn cull_mode(self, cull_mode: wgpu::CullMode) -> Self {
+        Self { cull_mode, ..self }
+    }
+
+    /// The defines the shader modules were compiled with, see `shaders`.
+    pub fn permutation(self, permutation: u32) -> Self {
+        Self { permutation, ..self }
+    }
+
+    pub fn key(&self) -> PipelineKey {
+        PipelineKey {
+            label: self.label,
+            vertex_shader: self.vertex_shader.label.unwrap_or_default(),
+            fragment_shader: self.fragment_shader.label.unwrap_or_default(),
+            vertex_buffers: self.vertex_buffers
+                .iter()
+                .map(|layout| (layout.array_stride, layout.step_mode, layout.attributes))
+                .collect(),
+            bind_group_layouts: self.bind_group_layouts
+                .iter()
+                .map(|layout| layout.entries().to_vec())
+                .collect(),
+            format: self.format,
+            blend: self.blend,
+            cull_mode: self.cull_mode,
+            permutation: self.permutation
+        }
+    }
+
+    pub fn build(&self, device: &wgpu::Device) -> wgpu::RenderPipeline {
+        let vs_module = device.create_shader_module(self.vertex_shader);
+        let fs_module = device.create_shader_module(self.fragment_shader);
+        let bind_group_layouts = self.bind_group_layouts.iter().map(|&layout| &**layout).collect::<Vec<_>>();
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some(self.label),
+            bind_group_layouts: &bind_group_layouts,
+            push_constant_ranges: &[],
+        });
+
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some(self.label),
+            layout: Some(&pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &vs_module,
+                entry_point: "main",
+                buffers: &self.vertex_buffers,
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &fs_module,
+                entry_point: "main",
+                targets: &[wgpu::ColorTargetState {
+                    format: self.format,
+                    color_blend: self.blend.color_blend(),
+                    alpha_blend: self.blend.alpha_blend(),
+                    write_mask: wgpu::ColorWrite::ALL,
+                }],
+            }),
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                strip_index_format: None,
+                front_face: wgpu::FrontFace::Ccw,
+                cull_mode: self.cull_mode,
+                // Setting this to anything other than Fill requires Features::NON_FILL_POLYGON_MODE
+                polygon_mode: wgpu::PolygonMode::Fill,
+            },
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState {
+                count: 1,
+                mask: !0,
+                alpha_to_coverage_enabled: false,
+            },
+        })
+    }
+
+}
+
+/// Pipelines built so far, shared between everything that draws with the same key.
+#[derive(Default)]
+pub struct PipelineCache {
+    pipelines: HashMap<PipelineKey, Rc<wgpu::RenderPipeline>>
+}
+
+impl PipelineCache {
+
+    pub fn get_or_build(&mut self, device: &wgpu::Device, builder: &PipelineBuilder) -> Rc<wgpu::RenderPipeline> {
+        self.pipelines
+            .entry(builder.key())
+            .or_insert_with(|| {
+                log::info!("Building pipeline {:?}", builder.key());
+                Rc::new(builder.build(device))
+            })
+            .clone()
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::framework::tests::device;
+    use crate::{include_spirv_out, impl_vertex};
+    use super::*;
+
+    #[repr(C)]
+    #[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+    struct QuadVertex {
+        position: [f32; 2],
+        tex_coords: [f32; 2]
+    }
+
+    impl_vertex!(QuadVertex { position: Float2, tex_coords: Float2 });
+
+    /// Same stride as `QuadVertex` with different attributes.
+    #[repr(C)]
+    #[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+    struct ColorVertex {
+        color: [f32; 4]
+    }
+
+    impl_vertex!(ColorVertex { color: Float4 });
+
+    fn uniform_entry(visibility: wgpu::ShaderStage) -> wgpu::BindGroupLayoutEntry {
+        wgpu::BindGroupLayoutEntry {
+            binding: 0,
+            visibility,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        }
+    }
+
+    #[test]
+    fn keys_tell_layouts_apart() {
+        let (device, _) = match device() {
+            Some(device) => device,
+            None => return
+        };
+        let vertex_shader = include_spirv_out!("parallax.vert.spv");
+        let fragment_shader = include_spirv_out!("parallax.frag.spv");
+        let format = wgpu::TextureFormat::Bgra8UnormSrgb;
+        let vertex_layout = BindGroupLayout::new(&device, "layout", &[uniform_entry(wgpu::ShaderStage::VERTEX)]);
+        let same_layout = BindGroupLayout::new(&device, "layout", &[uniform_entry(wgpu::ShaderStage::VERTEX)]);
+        let fragment_layout = BindGroupLayout::new(&device, "layout", &[uniform_entry(wgpu::ShaderStage::FRAGMENT)]);
+        let builder = || PipelineBuilder::new("Pipeline", &vertex_shader, &fragment_shader, format);
+
+        let key = builder().vertex::<QuadVertex>(wgpu::InputStepMode::Vertex).bind_group(&vertex_layout).key();
+        // Layouts with the same entries create the same pipeline layout, so they may share pipelines
+        assert_eq!(key, builder().vertex::<QuadVertex>(wgpu::InputStepMode::Vertex).bind_group(&same_layout).key());
+        assert_ne!(key, builder().vertex::<QuadVertex>(wgpu::InputStepMode::Vertex).bind_group(&fragment_layout).key());
+        assert_ne!(key, builder().vertex::<ColorVertex>(wgpu::InputStepMode::Vertex).bind_group(&vertex_layout).key());
+        assert_ne!(key, builder().vertex::<QuadVertex>(wgpu::InputStepMode::Instance).bind_group(&vertex_layout).key());
+        assert_ne!(key, builder().bind_group(&vertex_layout).key());
+    }
+}
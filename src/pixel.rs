@@ -0,0 +1,222 @@
+use crate::mipmap::{srgb_to_linear, linear_to_srgb};
+
+/// A texel type that can be stored in a `TextureData` and uploaded as a texture of `FORMAT`.
+pub trait Pixel: bytemuck::Pod {
+    const FORMAT: wgpu::TextureFormat;
+    /// Integer formats hold ids and the like, their mip levels pick a texel instead of averaging.
+    const FILTERABLE: bool = true;
+    /// Formats with alpha are filtered with premultiplied alpha.
+    const HAS_ALPHA: bool = false;
+
+    /// Whether the pixel data can be uploaded into a texture of `format`.
+    fn is_compatible(format: wgpu::TextureFormat) -> bool {
+        format == Self::FORMAT
+    }
+
+    /// Converts to linear rgba, missing channels are zero and a missing alpha is one.
+    fn to_linear(&self) -> [f32; 4];
+
+    fn from_linear(value: [f32; 4]) -> Self;
+}
+
+fn unorm8(value: u8) -> f32 {
+    value as f32 / 255.0
+}
+
+fn to_unorm8(value: f32) -> u8 {
+    (value.clamp(0.0, 1.0) * 255.0 + 0.5) as u8
+}
+
+/// R8
+impl Pixel for u8 {
+    const FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::R8Unorm;
+
+    fn to_linear(&self) -> [f32; 4] {
+        [unorm8(*self), 0.0, 0.0, 1.0]
+    }
+
+    fn from_linear(value: [f32; 4]) -> Self {
+        to_unorm8(value[0])
+    }
+}
+
+/// RG8
+impl Pixel for [u8; 2] {
+    const FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rg8Unorm;
+
+    fn to_linear(&self) -> [f32; 4] {
+        [unorm8(self[0]), unorm8(self[1]), 0.0, 1.0]
+    }
+
+    fn from_linear(value: [f32; 4]) -> Self {
+        [to_unorm8(value[0]), to_unorm8(value[1])]
+    }
+}
+
+/// R16Uint
+impl Pixel for u16 {
+    const FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::R16Uint;
+    const FILTERABLE: bool = false;
+
+    fn to_linear(&self) -> [f32; 4] {
+        [*self as f32, 0.0, 0.0, 1.0]
+    }
+
+    fn from_linear(value: [f32; 4]) -> Self {
+        value[0].round().clamp(0.0, u16::MAX as f32) as u16
+    }
+}
+
+/// RGBA8 with srgb encoded color channels. Can be uploaded as linear RGBA8 too, mip filtering still assumes srgb.
+impl Pixel for [u8; 4] {
+    const FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8UnormSrgb;
+    const HAS_ALPHA: bool = true;
+
+    fn is_compatible(format: wgpu::TextureFormat) -> bool {
+        matches!(format, wgpu::TextureFormat::Rgba8UnormSrgb | wgpu::TextureFormat::Rgba8Unorm)
+    }
+
+    fn to_linear(&self) -> [f32; 4] {
+        [srgb_to_linear(unorm8(self[0])), srgb_to_linear(unorm8(self[1])), srgb_to_linear(unorm8(self[2])), unorm8(self[3])]
+    }
+
+    fn from_linear(value: [f32; 4]) -> Self {
+        [
+            to_unorm8(linear_to_srgb(value[0].clamp(0.0, 1.0))),
+            to_unorm8(linear_to_srgb(value[1].clamp(0.0, 1.0))),
+            to_unorm8(linear_to_srgb(value[2].clamp(0.0, 1.0))),
+            to_unorm8(value[3])
+        ]
+    }
+}
+
+/// RGBA16F, stored as the raw half float bits.
+#[allow(dead_code)]
+#[repr(transparent)]
+#[derive(Copy, Clone, Debug, Default, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct Rgba16F(pub [u16; 4]);
+
+impl Pixel for Rgba16F {
+    const FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+    const HAS_ALPHA: bool = true;
+
+    fn to_linear(&self) -> [f32; 4] {
+        [f16_to_f32(self.0[0]), f16_to_f32(self.0[1]), f16_to_f32(self.0[2]), f16_to_f32(self.0[3])]
+    }
+
+    fn from_linear(value: [f32; 4]) -> Self {
+        Rgba16F([f32_to_f16(value[0]), f32_to_f16(value[1]), f32_to_f16(value[2]), f32_to_f16(value[3])])
+    }
+}
+
+/// RGBA32F
+impl Pixel for [f32; 4] {
+    const FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba32Float;
+    const HAS_ALPHA: bool = true;
+
+    fn to_linear(&self) -> [f32; 4] {
+        *self
+    }
+
+    fn from_linear(value: [f32; 4]) -> Self {
+        value
+    }
+}
+
+pub fn f16_to_f32(bits: u16) -> f32 {
+    let sign = if bits & 0x8000 != 0 { -1.0 } else { 1.0 };
+    let exponent = ((bits >> 10) & 0x1f) as i32;
+    let mantissa = (bits & 0x3ff) as f32;
+    sign * match exponent {
+        0 => mantissa * f32::powi(2.0, -24),
+        0x1f if mantissa == 0.0 => f32::INFINITY,
+        0x1f => f32::NAN,
+        _ => (1.0 + mantissa / 1024.0) * f32::powi(2.0, exponent - 15)
+    }
+}
+
+/// Rounds to the nearest half float, values out of range become infinity.
+pub fn f32_to_f16(value: f32) -> u16 {
+    let bits = value.to_bits();
+    let sign = ((bits >> 16) & 0x8000) as u16;
+    if value.is_nan() {
+        return sign | 0x7e00;
+    }
+    let abs = value.abs();
+    if abs >= 65520.0 {
+        return sign | 0x7c00;
+    }
+    if abs < f32::powi(2.0, -14) {
+        // Subnormal halves are multiples of 2^-24
+        return sign | (abs * f32::powi(2.0, 24)).round() as u16;
+    }
+    let exponent = ((bits >> 23) & 0xff) as i32 - 127;
+    let mantissa = bits & 0x7f_ffff;
+    let mut half = (((exponent + 15) as u32) << 10) | (mantissa >> 13);
+    // Round to nearest even, a mantissa overflow carries into the exponent
+    let rest = mantissa & 0x1fff;
+    if rest > 0x1000 || (rest == 0x1000 && half & 1 == 1) {
+        half += 1;
+    }
+    sign | half as u16
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn converts_zero() {
+        assert_eq!(f32_to_f16(0.0), 0x0000);
+        assert_eq!(f32_to_f16(-0.0), 0x8000);
+        assert_eq!(f16_to_f32(0x0000), 0.0);
+        assert!(f16_to_f32(0x8000).is_sign_negative());
+    }
+
+    #[test]
+    fn converts_subnormals() {
+        assert_eq!(f32_to_f16(f32::powi(2.0, -24)), 0x0001);
+        assert_eq!(f32_to_f16(1023.0 * f32::powi(2.0, -24)), 0x03ff);
+        assert_eq!(f32_to_f16(f32::powi(2.0, -26)), 0x0000);
+        assert_eq!(f16_to_f32(0x0001), f32::powi(2.0, -24));
+        assert_eq!(f16_to_f32(0x83ff), -1023.0 * f32::powi(2.0, -24));
+    }
+
+    #[test]
+    fn converts_infinity() {
+        assert_eq!(f32_to_f16(f32::INFINITY), 0x7c00);
+        assert_eq!(f32_to_f16(f32::NEG_INFINITY), 0xfc00);
+        assert_eq!(f32_to_f16(65504.0), 0x7bff);
+        assert_eq!(f32_to_f16(65520.0), 0x7c00);
+        assert_eq!(f16_to_f32(0x7c00), f32::INFINITY);
+        assert_eq!(f16_to_f32(0xfc00), f32::NEG_INFINITY);
+    }
+
+    #[test]
+    fn converts_nan() {
+        let half = f32_to_f16(f32::NAN);
+        assert_eq!(half & 0x7c00, 0x7c00);
+        assert_ne!(half & 0x03ff, 0);
+        assert!(f16_to_f32(0x7e00).is_nan());
+        assert!(f16_to_f32(0x7c01).is_nan());
+    }
+
+    #[test]
+    fn rounds_to_nearest_even() {
+        let ulp = f32::powi(2.0, -10);
+        assert_eq!(f32_to_f16(1.0), 0x3c00);
+        assert_eq!(f32_to_f16(1.0 + ulp * 0.5), 0x3c00);
+        assert_eq!(f32_to_f16(1.0 + ulp * 1.5), 0x3c02);
+        assert_eq!(f32_to_f16(1.0 + ulp * 0.5 + f32::powi(2.0, -20)), 0x3c01);
+        // The mantissa overflow carries into the exponent
+        assert_eq!(f32_to_f16(2.0 - f32::powi(2.0, -12)), 0x4000);
+    }
+
+    #[test]
+    fn round_trips_every_finite_half() {
+        for bits in (0..0x7c00).chain(0x8000..0xfc00) {
+            assert_eq!(f32_to_f16(f16_to_f32(bits)), bits, "{:#06x}", bits);
+        }
+    }
+
+}
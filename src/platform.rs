@@ -0,0 +1,175 @@
+use glam::*;
+use serde::Deserialize;
+use crate::catalog::Catalog;
+use crate::physics::{Aabb, Body};
+use crate::tilemap::TileGrid;
+
+#[derive(Deserialize, Copy, Clone, Debug, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Easing {
+    Linear,
+    /// Slows down when approaching and leaving a node.
+    EaseInOut
+}
+
+impl Easing {
+    pub fn apply(self, t: f32) -> f32 {
+        match self {
+            Easing::Linear => t,
+            Easing::EaseInOut => t * t * (3.0 - 2.0 * t)
+        }
+    }
+}
+
+#[derive(Deserialize, Copy, Clone, Debug, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum PathMode {
+    /// Travels back along the path after reaching the last node.
+    PingPong,
+    /// Travels from the last node straight back to the first one.
+    Loop
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct PlatformKind {
+    pub size: [f32; 2],
+    /// Speed in tiles per second.
+    pub speed: f32,
+    #[serde(default = "default_easing")]
+    pub easing: Easing,
+    #[serde(default = "default_mode")]
+    pub mode: PathMode,
+    /// Time in seconds the platform waits at each node.
+    #[serde(default)]
+    pub pause: f32,
+    #[serde(default = "default_tile")]
+    pub tile: i32,
+    #[serde(default = "default_color")]
+    pub color: [f32; 4]
+}
+
+fn default_easing() -> Easing {
+    Easing::Linear
+}
+
+fn default_mode() -> PathMode {
+    PathMode::PingPong
+}
+
+fn default_tile() -> i32 {
+    -1
+}
+
+fn default_color() -> [f32; 4] {
+    [1.0; 4]
+}
+
+pub type PlatformKinds = Catalog<PlatformKind>;
+
+/// A one way platform that moves along the nodes of its Ogmo entity.
+#[derive(Clone)]
+pub struct Platform {
+    pub kind: PlatformKind,
+    pub position: Vec2,
+    path: Vec<Vec2>,
+    segment: usize,
+    forward: bool,
+    progress: f32,
+    wait: f32
+}
+
+impl Platform {
+
+    pub fn new(kind: &PlatformKind, spawn: Vec2, nodes: &[Vec2]) -> Self {
+        let offset = vec2(kind.size[0] * 0.5, 0.0);
+        let path = std::iter::once(spawn)
+            .chain(nodes.iter().copied())
+            .map(|point| point - offset)
+            .collect::<Vec<_>>();
+        Self {
+            kind: kind.clone(),
+            position: path[0],
+            path,
+            segment: 0,
+            forward: true,
+            progress: 0.0,
+            wait: 0.0
+        }
+    }
+
+    pub fn aabb(&self) -> Aabb {
+        Aabb::new(self.position, Vec2::from(self.kind.size))
+    }
+
+    fn segment_points(&self) -> (Vec2, Vec2) {
+        let next = (self.segment + 1) % self.path.len();
+        if self.forward {
+            (self.path[self.segment], self.path[next])
+        } else {
+            (self.path[next], self.path[self.segment])
+        }
+    }
+
+    fn next_segment(&mut self) {
+        let last = self.path.len() - 1;
+        match self.kind.mode {
+            PathMode::Loop => self.segment = (self.segment + 1) % self.path.len(),
+            PathMode::PingPong => match (self.forward, self.segment) {
+                (true, s) if s + 1 == last => self.forward = false,
+                (true, s) => self.segment = s + 1,
+                (false, 0) => self.forward = true,
+                (false, s) => self.segment = s - 1
+            }
+        }
+        self.progress = 0.0;
+        self.wait = self.kind.pause;
+    }
+
+    /// Advances the platform and returns how far it moved.
+    pub fn update(&mut self, dt: f32) -> Vec2 {
+        if self.path.len() < 2 {
+            return Vec2::ZERO;
+        }
+        let mut dt = dt;
+        if self.wait > 0.0 {
+            let waited = self.wait.min(dt);
+            self.wait -= waited;
+            dt -= waited;
+        }
+        let (start, end) = self.segment_points();
+        let length = start.distance(end).max(f32::EPSILON);
+        self.progress += self.kind.speed * dt / length;
+        if self.progress >= 1.0 {
+            self.next_segment();
+            let previous = self.position;
+            self.position = end;
+            return self.position - previous;
+        }
+        let previous = self.position;
+        self.position = start.lerp(end, self.kind.easing.apply(self.progress));
+        self.position - previous
+    }
+
+}
+
+/// Moves `body` along with a solid that was at `before` and just moved by `delta`, if the body was standing on it.
+pub fn carry(before: &Aabb, delta: Vec2, body: &mut Body, grid: &TileGrid) {
+    if !body.is_standing_on(before) {
+        return;
+    }
+    let after = Aabb::new(before.min + delta, before.max - before.min);
+    let contacts = body.move_by(grid, delta);
+    if !contacts.ceiling && !contacts.floor {
+        body.position.y = after.max.y;
+        body.stand_on(&after);
+    }
+}
+
+/// Lets `body` land on any of the `platforms` it fell through since its bottom was at `previous_bottom`.
+pub fn land(body: &mut Body, previous_bottom: f32, platforms: &[Platform]) {
+    for platform in platforms {
+        if body.land_on(&platform.aabb(), previous_bottom) {
+            return;
+        }
+    }
+}
\ No newline at end of file
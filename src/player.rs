@@ -0,0 +1,89 @@
+use glam::*;
+use winit::event::VirtualKeyCode;
+use crate::input::Input;
+use crate::physics::Body;
+use crate::tilemap::TileGrid;
+
+const RUN_SPEED: f32 = 8.0;
+const ACCELERATION: f32 = 60.0;
+const JUMP_SPEED: f32 = 17.0;
+const JUMP_CUT: f32 = 0.5;
+const COYOTE_TIME: f32 = 0.1;
+const INVULNERABILITY_TIME: f32 = 1.5;
+const KNOCKBACK: Vec2 = const_vec2!([6.0, 8.0]);
+const STOMP_BOUNCE: f32 = 12.0;
+
+pub const MAX_HEALTH: u32 = 3;
+
+pub struct Player {
+    pub body: Body,
+    pub health: u32,
+    invulnerable: f32,
+    air_time: f32
+}
+
+impl Player {
+
+    pub fn new(spawn: Vec2) -> Self {
+        Self {
+            body: Body::standing_at(spawn, vec2(0.8, 0.9)),
+            health: MAX_HEALTH,
+            invulnerable: 0.0,
+            air_time: 0.0
+        }
+    }
+
+    pub fn update(&mut self, input: &Input, grid: &TileGrid, dt: f32) {
+        self.invulnerable = (self.invulnerable - dt).max(0.0);
+
+        let direction = input.axis(VirtualKeyCode::Left, VirtualKeyCode::Right) +
+            input.axis(VirtualKeyCode::A, VirtualKeyCode::D);
+        let target = direction.max(-1.0).min(1.0) * RUN_SPEED;
+        let max_change = ACCELERATION * dt;
+        self.body.velocity.x += (target - self.body.velocity.x).max(-max_change).min(max_change);
+
+        if self.body.contacts.floor {
+            self.air_time = 0.0;
+        } else {
+            self.air_time += dt;
+        }
+
+        let jump = input.is_pressed(VirtualKeyCode::Space) || input.is_pressed(VirtualKeyCode::Up) || input.is_pressed(VirtualKeyCode::W);
+        let jump_held = input.is_held(VirtualKeyCode::Space) || input.is_held(VirtualKeyCode::Up) || input.is_held(VirtualKeyCode::W);
+        if jump && self.air_time < COYOTE_TIME {
+            self.body.velocity.y = JUMP_SPEED;
+            self.air_time = COYOTE_TIME;
+        }
+        if !jump_held {
+            self.body.velocity.y = self.body.velocity.y.min(JUMP_SPEED * JUMP_CUT);
+        }
+
+        self.body.apply_gravity(dt);
+        self.body.move_and_collide(grid, dt);
+    }
+
+    pub fn is_invulnerable(&self) -> bool {
+        self.invulnerable > 0.0
+    }
+
+    /// Applies `damage` and knocks the player away from `source`. Returns `false` if the player was still invulnerable.
+    pub fn hurt(&mut self, damage: u32, source: Vec2) -> bool {
+        if self.is_invulnerable() {
+            return false;
+        }
+        self.health = self.health.saturating_sub(damage);
+        self.invulnerable = INVULNERABILITY_TIME;
+        let direction = if self.body.center().x < source.x { -1.0 } else { 1.0 };
+        self.body.velocity = vec2(KNOCKBACK.x * direction, KNOCKBACK.y);
+        true
+    }
+
+    pub fn bounce(&mut self) {
+        self.body.velocity.y = STOMP_BOUNCE;
+    }
+
+    pub fn is_visible(&self, time: f32) -> bool {
+        !self.is_invulnerable() || (time * 10.0) as u32 % 2 == 0
+    }
+
+}
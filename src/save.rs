@@ -0,0 +1,178 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::{create_dir_all, rename, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use anyhow::*;
+use serde::{Serialize, Deserialize};
+use serde_json::Value;
+
+pub const CURRENT_VERSION: u32 = 1;
+
+type Migration = fn(&mut Value) -> Result<(), Error>;
+
+/// Upgrades a save game of version `i + 1` to version `i + 2`. A new entry has to be added here
+/// whenever `CURRENT_VERSION` is increased, so old save games keep loading.
+const MIGRATIONS: [Migration; CURRENT_VERSION as usize - 1] = [];
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(default)]
+pub struct Settings {
+    pub fullscreen: bool,
+    pub show_debug_ui: bool
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            fullscreen: false,
+            show_debug_ui: true
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Default)]
+#[serde(default)]
+pub struct LevelProgress {
+    /// Fastest completion in seconds.
+    pub best_time: Option<f32>,
+    /// Indices of the collectibles of the level that have been collected in any completed run.
+    pub collected: BTreeSet<usize>
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct SaveGame {
+    pub version: u32,
+    pub unlocked_levels: BTreeSet<String>,
+    pub levels: BTreeMap<String, LevelProgress>,
+    pub settings: Settings
+}
+
+impl SaveGame {
+
+    pub fn new(first_level: &str) -> Self {
+        Self {
+            version: CURRENT_VERSION,
+            unlocked_levels: std::iter::once(first_level.to_string()).collect(),
+            levels: BTreeMap::new(),
+            settings: Settings::default()
+        }
+    }
+
+    /// The save game location inside the data directory of the current user.
+    pub fn default_path() -> Option<PathBuf> {
+        dirs::data_dir().map(|dir| dir.join(env!("CARGO_PKG_NAME")).join("save.json"))
+    }
+
+    /// Returns `None` if there is no save game at `path` yet.
+    pub fn load(path: &Path) -> Result<Option<Self>, Error> {
+        if !path.exists() {
+            return Ok(None);
+        }
+        let json = std::fs::read_to_string(path).with_context(|| format!("Can't read {}", path.display()))?;
+        Ok(Some(Self::from_json(&json).with_context(|| format!("Can't parse {}", path.display()))?))
+    }
+
+    pub fn from_json(json: &str) -> Result<Self, Error> {
+        let mut value: Value = serde_json::from_str(json)?;
+        migrate(&mut value)?;
+        Ok(serde_json::from_value(value)?)
+    }
+
+    pub fn to_json(&self) -> Result<String, Error> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+
+    /// Writes to a temporary file next to `path` first and then swaps it in,
+    /// so a crash while saving never leaves a half written save game behind.
+    pub fn save(&self, path: &Path) -> Result<(), Error> {
+        if let Some(dir) = path.parent() {
+            create_dir_all(dir)?;
+        }
+        let temp_path = path.with_extension("json.tmp");
+        {
+            let mut file = File::create(&temp_path)?;
+            file.write_all(self.to_json()?.as_bytes())?;
+            file.sync_all()?;
+        }
+        rename(&temp_path, path)?;
+        Ok(())
+    }
+
+    pub fn record_completion(&mut self, level: &str, time: f32, collected: impl IntoIterator<Item=usize>, next_level: Option<&str>) {
+        let progress = self.levels.entry(level.to_string()).or_default();
+        progress.best_time = Some(progress.best_time.map_or(time, |best| best.min(time)));
+        progress.collected.extend(collected);
+        if let Some(next_level) = next_level {
+            self.unlocked_levels.insert(next_level.to_string());
+        }
+    }
+
+}
+
+fn migrate(value: &mut Value) -> Result<(), Error> {
+    apply_migrations(value, &MIGRATIONS)
+}
+
+/// Brings `value` up to version `migrations.len() + 1` by running the migrations it hasn't seen yet.
+fn apply_migrations(value: &mut Value, migrations: &[Migration]) -> Result<(), Error> {
+    let current_version = migrations.len() as u32 + 1;
+    let version = value.get("version")
+        .and_then(Value::as_u64)
+        .context("The save game has no version")? as u32;
+    ensure!(version >= 1, "Invalid save game version {}", version);
+    ensure!(version <= current_version, "The save game version {} is newer than the supported version {}", version, current_version);
+    for migration in &migrations[(version - 1) as usize..] {
+        migration(value)?;
+    }
+    value["version"] = current_version.into();
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn round_trip() {
+        let mut save = SaveGame::new("level1");
+        save.record_completion("level1", 42.5, vec![0, 3], Some("level2"));
+        save.settings.fullscreen = true;
+        assert_eq!(SaveGame::from_json(&save.to_json().unwrap()).unwrap(), save);
+    }
+
+    #[test]
+    fn rejects_unknown_versions() {
+        let mut json = serde_json::to_value(SaveGame::new("level1")).unwrap();
+        json["version"] = 0.into();
+        assert!(SaveGame::from_json(&json.to_string()).is_err());
+        json["version"] = (CURRENT_VERSION + 1).into();
+        assert!(SaveGame::from_json(&json.to_string()).is_err());
+    }
+
+    #[test]
+    fn runs_pending_migrations() {
+        // A version 2 that renamed `unlocked` to `unlocked_levels` and a version 3 that added the settings
+        let migrations: [Migration; 2] = [
+            |value| {
+                let unlocked = value.as_object_mut().unwrap().remove("unlocked").context("No unlocked levels")?;
+                value["unlocked_levels"] = unlocked;
+                Ok(())
+            },
+            |value| {
+                value["settings"] = serde_json::to_value(Settings::default())?;
+                Ok(())
+            }
+        ];
+        let mut value = json!({ "version": 1, "unlocked": ["level1"], "levels": {} });
+        apply_migrations(&mut value, &migrations).unwrap();
+        assert_eq!(value, json!({ "version": 3, "unlocked_levels": ["level1"], "levels": {}, "settings": serde_json::to_value(Settings::default()).unwrap() }));
+
+        // Only the second migration is still pending
+        let mut value = json!({ "version": 2, "unlocked_levels": [], "levels": {} });
+        apply_migrations(&mut value, &migrations).unwrap();
+        assert_eq!(value["version"], 3);
+        assert!(value.get("settings").is_some());
+    }
+
+}
@@ -2,6 +2,10 @@
 
 #extension GL_EXT_samplerless_texture_functions : require
 
+// permutations: DEBUG_GRID PIXEL_ART
+
+#include "tile.glsl"
+
 layout(location=0) in vec2 v_tex_coords;
 layout(location=0) out vec4 f_color;
 
@@ -9,15 +13,95 @@ layout(set = 0, binding = 0) uniform texture2DArray t_diffuse_array;
 layout(set = 0, binding = 1) uniform utexture2D t_placement;
 layout(set = 0, binding = 2) uniform sampler s_diffuse;
 
+// The first entry holds the number of tiles with an entry. Entry 1 + tile is the index of the first frame
+// of that tile, its frame count (0 for static tiles) and the frame duration in milliseconds.
+// The frames follow with one tile per entry. Built by `animation_table` in chunk.rs
+layout(set = 3, binding = 0) readonly buffer Animations {
+    uvec4 animations[];
+};
+
+// Milliseconds since the level started. Integers keep every frame duration exact until they wrap after 49 days
+layout(set = 2, binding = 0) uniform Time {
+    uint time;
+};
+
+// The low bits of a placement are the tile id + 1, the high bits its transform.
+// Has to match PLACEMENT_ID_BITS and the transform bits in chunk.rs and tilemap.rs
+const uint PLACEMENT_ID_BITS = 13u;
+const uint FLIP_X = 1u;
+const uint FLIP_Y = 2u;
+const uint ROTATE_90 = 4u;
+
+// Maps a position inside the drawn tile and its derivatives to the tile image. The tile is rotated
+// first and mirrored afterwards, so the mirroring is undone first
+void apply_transform(uint transform, inout vec2 local, inout vec2 dx, inout vec2 dy) {
+    if((transform & FLIP_X) != 0u) {
+        local.x = 1.0 - local.x;
+        dx.x = -dx.x;
+        dy.x = -dy.x;
+    }
+    if((transform & FLIP_Y) != 0u) {
+        local.y = 1.0 - local.y;
+        dx.y = -dx.y;
+        dy.y = -dy.y;
+    }
+    if((transform & ROTATE_90) != 0u) {
+        local = vec2(local.y, 1.0 - local.x);
+        dx = vec2(dx.y, -dx.x);
+        dy = vec2(dy.y, -dy.x);
+    }
+}
+
+// The frame of an animated tile that is shown at the moment
+uint animate(uint tile) {
+    if(tile >= animations[0].x)
+        return tile;
+    uvec4 animation = animations[1u + tile];
+    if(animation.y == 0u)
+        return tile;
+    uint frame = time / animation.z % animation.y;
+    return animations[animation.x + frame].x;
+}
+
+#ifdef DEBUG_GRID
+const vec4 GRID_COLOR = vec4(1.0, 1.0, 1.0, 0.3);
+#endif
+
 void main() {
     vec2 scaledTexCoord =  v_tex_coords * textureSize(t_placement, 0);
-    uint id = texelFetch(t_placement, ivec2(scaledTexCoord), 0).r;
-    if(id == 0)
+#ifdef DEBUG_GRID
+    // One pixel wide lines along the tile edges, derived before any branch
+    vec2 cell = fract(scaledTexCoord);
+    bool on_grid = any(lessThan(cell, fwidth(scaledTexCoord)));
+#endif
+    uint placement = texelFetch(t_placement, ivec2(scaledTexCoord), 0).r;
+    uint id = placement & ((1u << PLACEMENT_ID_BITS) - 1u);
+    if(id == 0u) {
+#ifdef DEBUG_GRID
+        if(on_grid) {
+            f_color = GRID_COLOR;
+            return;
+        }
+#endif
         discard;
+    }
 
+    vec2 layer_size = vec2(textureSize(t_diffuse_array, 0).xy);
+    vec2 inner = (layer_size - 2.0 * TILE_EXTRUSION) / layer_size;
+    vec2 local = fract(scaledTexCoord);
     vec2 dx = dFdx(scaledTexCoord);
     vec2 dy = dFdy(scaledTexCoord);
-    vec2 local_tex = fract(scaledTexCoord);
+    apply_transform(placement >> PLACEMENT_ID_BITS, local, dx, dy);
+    dx *= inner;
+    dy *= inner;
+    vec2 local_tex = tile_uv(local, layer_size);
+#ifdef PIXEL_ART
+    local_tex = pixel_art_uv(local_tex, layer_size, dx, dy);
+#endif
 
-    f_color = textureGrad(sampler2DArray(t_diffuse_array, s_diffuse), vec3(local_tex, float(id - 1)), dx, dy);
-}
\ No newline at end of file
+    f_color = textureGrad(sampler2DArray(t_diffuse_array, s_diffuse), vec3(local_tex, float(animate(id - 1u))), dx, dy);
+#ifdef DEBUG_GRID
+    if(on_grid)
+        f_color = mix(f_color, vec4(GRID_COLOR.rgb, 1.0), GRID_COLOR.a);
+#endif
+}
@@ -0,0 +1,5 @@
+//! Shader variants compiled by `build.rs`. A shader with a `// permutations: DEBUG_GRID PIXEL_ART` line gets a
+//! module named after its file, e.g. `shader_frag`, with a bit per define and `descriptor(bits)` to pick a variant.
+#![allow(dead_code)]
+
+include!(concat!(env!("OUT_DIR"), "/shaders.rs"));
@@ -0,0 +1,23 @@
+#version 450
+
+layout(location=0) in vec2 v_tex_coords;
+layout(location=1) in vec4 v_color;
+layout(location=2) flat in int v_tile;
+
+layout(location=0) out vec4 f_color;
+
+layout(set = 0, binding = 0) uniform texture2DArray t_diffuse_array;
+layout(set = 0, binding = 1) uniform sampler s_diffuse;
+
+#include "tile.glsl"
+
+void main() {
+    // Negative tile ids draw a plain colored rectangle
+    if (v_tile < 0) {
+        f_color = v_color;
+    } else {
+        vec2 layer_size = vec2(textureSize(sampler2DArray(t_diffuse_array, s_diffuse), 0).xy);
+        vec2 tex_coords = tile_uv(v_tex_coords, layer_size);
+        f_color = texture(sampler2DArray(t_diffuse_array, s_diffuse), vec3(tex_coords, float(v_tile))) * v_color;
+    }
+}
\ No newline at end of file
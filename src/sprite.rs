@@ -0,0 +1,169 @@
+use glam::*;
+use std::rc::Rc;
+use crate::framework::Display;
+use crate::buffer::{UniformBuffer, BindUniformBuffer};
+use crate::include_spirv_out;
+use crate::bindings::{self, BindGroupLayout};
+use crate::atlas::AtlasRegion;
+use crate::mesh::VertexBuffer;
+use crate::pipeline::{PipelineBuilder, BlendMode};
+use crate::impl_vertex;
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct Sprite {
+    pub position: Vec2,
+    pub size: Vec2,
+    pub color: [f32; 4],
+    pub tile: i32,
+    /// Min and max corner of the part of the layer that is drawn.
+    pub uv: [f32; 4]
+}
+
+impl_vertex!(Sprite { position: Float2, size: Float2, color: Float4, tile: Int, uv: Float4 });
+
+const FULL_LAYER: [f32; 4] = [0.0, 0.0, 1.0, 1.0];
+
+impl Sprite {
+
+    pub fn tile(position: Vec2, size: Vec2, tile: i32) -> Self {
+        Self {
+            position,
+            size,
+            color: [1.0; 4],
+            tile,
+            uv: FULL_LAYER
+        }
+    }
+
+    /// A sprite of an atlas, drawn by a `SpriteRenderer` that samples the atlas pages.
+    pub fn region(position: Vec2, size: Vec2, region: &AtlasRegion) -> Self {
+        Self {
+            position,
+            size,
+            color: [1.0; 4],
+            tile: region.page as i32,
+            uv: region.uv
+        }
+    }
+
+    pub fn rect(position: Vec2, size: Vec2, color: [f32; 4]) -> Self {
+        Self {
+            position,
+            size,
+            color,
+            tile: -1,
+            uv: FULL_LAYER
+        }
+    }
+
+    pub fn with_color(self, color: [f32; 4]) -> Self {
+        Self {
+            color,
+            ..self
+        }
+    }
+
+}
+
+/// A growable list of sprites that gets uploaded to the gpu as one instance buffer.
+pub struct SpriteBatch {
+    sprites: Vec<Sprite>,
+    buffer: VertexBuffer<Sprite>
+}
+
+impl SpriteBatch {
+
+    pub fn new(device: &wgpu::Device, capacity: usize) -> Self {
+        Self {
+            sprites: Vec::with_capacity(capacity),
+            buffer: VertexBuffer::new(device, "Sprite Buffer", capacity)
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.sprites.clear();
+    }
+
+    pub fn push(&mut self, sprite: Sprite) {
+        self.sprites.push(sprite);
+    }
+
+    pub fn upload(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) {
+        self.buffer.write(device, queue, &self.sprites);
+    }
+
+}
+
+impl Extend<Sprite> for SpriteBatch {
+    fn extend<I: IntoIterator<Item=Sprite>>(&mut self, iter: I) {
+        self.sprites.extend(iter)
+    }
+}
+
+pub struct SpriteRenderer {
+    render_pipeline: Rc<wgpu::RenderPipeline>,
+    bind_group: wgpu::BindGroup
+}
+
+impl SpriteRenderer {
+
+    pub fn new(display: &Display, texture_view: &wgpu::TextureView, camera_layout: &BindGroupLayout) -> Self {
+        let vertex_shader = include_spirv_out!("sprite.vert.spv");
+        let fragment_shader = include_spirv_out!("sprite.frag.spv");
+
+        let sampler = display.device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+
+        let bind_group_layout = BindGroupLayout::new(&display.device, "sprite_bind_group_layout", bindings::sprite::set0::ENTRIES);
+
+        let bind_group = display.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: bindings::sprite::set0::T_DIFFUSE_ARRAY,
+                    resource: wgpu::BindingResource::TextureView(texture_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: bindings::sprite::set0::S_DIFFUSE,
+                    resource: wgpu::BindingResource::Sampler(&sampler),
+                },
+            ],
+            label: Some("sprite_bind_group"),
+        });
+
+        let render_pipeline = display.pipeline(
+            &PipelineBuilder::new("Sprite Pipeline", &vertex_shader, &fragment_shader, display.sc_desc.format)
+                .vertex::<Sprite>(wgpu::InputStepMode::Instance)
+                .bind_group(&bind_group_layout)
+                .bind_group(camera_layout)
+                .blend(BlendMode::Alpha)
+                // Screen space cameras flip the y axis and with it the winding order
+                .cull_mode(wgpu::CullMode::None)
+        );
+
+        Self {
+            render_pipeline,
+            bind_group
+        }
+    }
+
+    pub fn render<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>, batch: &'a SpriteBatch, camera: &'a UniformBuffer<Mat4>) {
+        if batch.buffer.is_empty() {
+            return;
+        }
+        render_pass.set_pipeline(&self.render_pipeline);
+        render_pass.set_vertex_buffer(0, batch.buffer.slice());
+        render_pass.set_bind_group(0, &self.bind_group, &[]);
+        render_pass.set_uniform_buffer(1, camera);
+        render_pass.draw(0..6, 0..batch.buffer.len());
+    }
+
+}
@@ -0,0 +1,29 @@
+#version 450
+
+layout(location=0) in vec2 i_position;
+layout(location=1) in vec2 i_size;
+layout(location=2) in vec4 i_color;
+layout(location=3) in int i_tile;
+layout(location=4) in vec4 i_uv;
+
+layout(location=0) out vec2 v_tex_coords;
+layout(location=1) out vec4 v_color;
+layout(location=2) flat out int v_tile;
+
+layout(set=1, binding=0)
+uniform Uniforms {
+    mat4 cam;
+};
+
+const vec2 corners[6] = vec2[6](
+    vec2(0.0, 0.0), vec2(1.0, 0.0), vec2(1.0, 1.0),
+    vec2(0.0, 0.0), vec2(1.0, 1.0), vec2(0.0, 1.0)
+);
+
+void main() {
+    vec2 corner = corners[gl_VertexIndex];
+    v_tex_coords = mix(i_uv.xy, i_uv.zw, vec2(corner.x, 1.0 - corner.y));
+    v_color = i_color;
+    v_tile = i_tile;
+    gl_Position = cam * vec4(i_position + corner * i_size, 0.0, 1.0);
+}
\ No newline at end of file
@@ -1,17 +1,30 @@
 use wgpu::util::DeviceExt;
 use wgpu::Extent3d;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use anyhow::*;
 use image::GenericImageView;
+use crate::mipmap::{MipFilter, LinearImage, resample_depth};
+use crate::pixel::Pixel;
+use crate::mipgen::MipGenerator;
 
-#[allow(dead_code)]
 pub enum MipMaps {
     None,
     Some(u32),
     All
 }
 
-pub struct TextureData<T> where T : bytemuck::Pod{
+#[allow(dead_code)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum TextureKind {
+    D1,
+    D2,
+    D2Array,
+    Cube,
+    D3
+}
+
+pub struct TextureData<T> where T : Pixel{
+    kind: TextureKind,
     width: u32,
     height: u32,
     depth: u32,
@@ -20,86 +33,123 @@ pub struct TextureData<T> where T : bytemuck::Pod{
     pixels: Box<[T]>
 }
 
+/// Border in pixels that every tile is extruded by inside its array layer. Linear filtering near the tile
+/// edges then only ever sees copies of the edge texels. Has to match `TILE_EXTRUSION` in the shaders.
+pub const TILE_EXTRUSION: u32 = 2;
+
+/// Where the tiles are inside a tileset image.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct TileLayout {
+    pub tile_width: u32,
+    pub tile_height: u32,
+    /// Empty pixels between the image border and the first tile.
+    pub margin_x: u32,
+    pub margin_y: u32,
+    /// Empty pixels between neighbouring tiles.
+    pub spacing_x: u32,
+    pub spacing_y: u32,
+    /// Pixels the edge of every tile is repeated outwards by.
+    pub extrusion: u32
+}
+
+impl TileLayout {
+
+    /// The number of whole tiles in a row and column of an image of the given size.
+    pub fn tile_count(&self, width: u32, height: u32) -> (u32, u32) {
+        let count = |size: u32, margin: u32, spacing: u32, tile: u32| (size.saturating_sub(2 * margin) + spacing) / (tile + spacing);
+        (count(width, self.margin_x, self.spacing_x, self.tile_width), count(height, self.margin_y, self.spacing_y, self.tile_height))
+    }
+
+}
+
 impl TextureData<[u8; 4]> {
-    pub fn parse_tileset(path: &PathBuf, tile_w: u32, tile_h: u32) -> Result<TextureData<[u8; 4]>, Error>{
+    /// Every tile becomes one array layer of `tile + 2 * extrusion` pixels with its edges extruded.
+    /// Passing no filter leaves the mip levels empty, e.g. to generate them on the gpu.
+    pub fn parse_tileset(path: &PathBuf, layout: &TileLayout, filter: Option<MipFilter>) -> Result<TextureData<[u8; 4]>, Error>{
         let image = image::open(path)?;
-        let expand_x = image.width()  / tile_w;
-        let expand_y = image.height() / tile_h;
+        let (expand_x, expand_y) = layout.tile_count(image.width(), image.height());
+        ensure!(expand_x > 0 && expand_y > 0, "{} contains no {}x{} tiles", path.display(), layout.tile_width, layout.tile_height);
 
-        let mut image_data = TextureData::<[u8; 4]>::new(tile_w, tile_h, expand_x * expand_y, MipMaps::All);
+        let border = layout.extrusion;
+        let layer_w = layout.tile_width + 2 * border;
+        let layer_h = layout.tile_height + 2 * border;
+        let mut image_data = TextureData::<[u8; 4]>::with_kind(TextureKind::D2Array, layer_w, layer_h, expand_x * expand_y, MipMaps::All);
         image_data.depth_divisor = Some(expand_x);
 
         for (i, x, y) in (0..expand_y).flat_map(|y| (0..expand_x).map(move |x| (x + expand_x * y, x, y))) {
-            for (px, py) in (0..tile_h).flat_map(|y| (0..tile_w).map(move |x| (x, y))) {
-                *image_data.get_pixel_mut(px, py, i, 0) = image.get_pixel(x * tile_w + px,y * tile_h + py).0;
+            let origin_x = layout.margin_x + x * (layout.tile_width + layout.spacing_x);
+            let origin_y = layout.margin_y + y * (layout.tile_height + layout.spacing_y);
+            for (px, py) in (0..layer_h).flat_map(|y| (0..layer_w).map(move |x| (x, y))) {
+                let tx = (px as i32 - border as i32).clamp(0, layout.tile_width as i32 - 1) as u32;
+                let ty = (py as i32 - border as i32).clamp(0, layout.tile_height as i32 - 1) as u32;
+                *image_data.get_pixel_mut(px, py, i, 0) = image.get_pixel(origin_x + tx, origin_y + ty).0;
             }
         }
 
-        image_data.generate_mipmaps();
+        if let Some(filter) = filter {
+            image_data.generate_mipmaps(filter);
+        }
 
         Ok(image_data)
     }
 
-    pub fn generate_mipmaps(&mut self){
-        for layer in 0..self.depth() {
-            for mipmap in 1..self.mipmaps() {
-                for y in 0..self.mipmapped_height(mipmap){
-                    for x in 0..self.mipmapped_width(mipmap){
-                        *self.get_pixel_mut(x,y,layer, mipmap) = Self::average(&[
-                            self.get_pixel(2 * x + 0, 2 * y + 0, layer, mipmap - 1),
-                            self.get_pixel(2 * x + 1, 2 * y + 0, layer, mipmap - 1),
-                            self.get_pixel(2 * x + 1, 2 * y + 1, layer, mipmap - 1),
-                            self.get_pixel(2 * x + 0, 2 * y + 1, layer, mipmap - 1),
-                        ]);
-                    }
-                }
-            }
+    /// A single 2d image with its whole mip chain.
+    pub fn from_image(path: &Path, filter: MipFilter) -> Result<TextureData<[u8; 4]>, Error> {
+        let image = image::open(path).with_context(|| format!("Can't load {}", path.display()))?.to_rgba8();
+        let mut image_data = TextureData::<[u8; 4]>::new(image.width(), image.height(), 1, MipMaps::All);
+        for (x, y, pixel) in image.enumerate_pixels() {
+            *image_data.get_pixel_mut(x, y, 0, 0) = pixel.0;
         }
+        image_data.generate_mipmaps(filter);
+        Ok(image_data)
     }
-
-    fn average(pixels: &[&[u8; 4]]) -> [u8; 4] {
-        let mut accum = [0u32; 4];
-        for pixel in pixels {
-            for i in 0..accum.len() {
-                accum[i] += pixel[i] as u32;
-            }
-        }
-        let mut result = [0u8; 4];
-        for i in 0..accum.len() {
-            result[i] = (accum[i] / pixels.len() as u32) as u8;
-        }
-        result
-    }
-
 }
 
-impl<T> TextureData<T> where T : bytemuck::Pod{
+impl<T> TextureData<T> where T : Pixel{
 
-    fn max_mapmap_levels(width: u32, height: u32) -> u32 {
-        1 + f32::floor(f32::log2(u32::max(width, height) as f32)) as u32
+    fn max_mapmap_levels(width: u32, height: u32, depth: u32) -> u32 {
+        1 + f32::floor(f32::log2(width.max(height).max(depth) as f32)) as u32
     }
 
     fn mipmaped_size(width: u32, level: u32) -> u32{
         (width / (1 << level)).max(1)
     }
 
-    fn size_per_layer(width: u32, height: u32, levels: u32) -> u32{
-        (0u32..levels).map(|i| Self::mipmaped_size(width, i) * Self::mipmaped_size(height, i)).sum()
+    /// `volume_depth` is one for everything but 3d textures.
+    fn size_per_layer(width: u32, height: u32, volume_depth: u32, levels: u32) -> u32{
+        (0u32..levels).map(|i| Self::mipmaped_size(width, i) * Self::mipmaped_size(height, i) * Self::mipmaped_size(volume_depth, i)).sum()
     }
 
+    /// A 2d texture, or a 2d array if `depth` is larger than one.
     pub fn new(width: u32, height: u32, depth: u32, mipmaps: MipMaps) -> Self {
+        let kind = if depth == 1 { TextureKind::D2 } else { TextureKind::D2Array };
+        Self::with_kind(kind, width, height, depth, mipmaps)
+    }
+
+    /// `depth` is the number of layers, or the depth of the volume for 3d textures.
+    pub fn with_kind(kind: TextureKind, width: u32, height: u32, depth: u32, mipmaps: MipMaps) -> Self {
+        debug_assert!(match kind {
+            TextureKind::D1 => height == 1 && depth == 1 && matches!(mipmaps, MipMaps::None),
+            TextureKind::D2 => depth == 1,
+            TextureKind::D2Array | TextureKind::D3 => true,
+            TextureKind::Cube => width == height && depth == 6
+        });
+        let volume_depth = if kind == TextureKind::D3 { depth } else { 1 };
+        let max_levels = Self::max_mapmap_levels(width, height, volume_depth);
         let mipmaps= match mipmaps {
             MipMaps::None => 1,
-            MipMaps::Some(levels) => {debug_assert!((1u32..Self::max_mapmap_levels(width, height)).contains(&levels)); levels},
-            MipMaps::All=> Self::max_mapmap_levels(width, height)
+            MipMaps::Some(levels) => {debug_assert!((1u32..=max_levels).contains(&levels)); levels},
+            MipMaps::All=> max_levels
         };
+        let layers = if kind == TextureKind::D3 { 1 } else { depth };
         Self {
+            kind,
             width,
             height,
             depth,
             mipmaps,
             depth_divisor: None,
-            pixels: vec![T::zeroed(); (Self::size_per_layer(width, height, mipmaps) * depth) as usize].into_boxed_slice()
+            pixels: vec![T::zeroed(); (Self::size_per_layer(width, height, volume_depth, mipmaps) * layers) as usize].into_boxed_slice()
         }
     }
 
@@ -115,76 +165,155 @@ impl<T> TextureData<T> where T : bytemuck::Pod{
 
     pub fn mipmapped_height(&self, mipmap: u32) -> u32 {Self::mipmaped_size(self.height, mipmap) }
 
+    /// The depth of a level of a 3d texture, one for all other kinds.
+    pub fn mipmapped_depth(&self, mipmap: u32) -> u32 {
+        Self::mipmaped_size(self.volume_depth(), mipmap)
+    }
+
     pub fn mipmaps(&self) -> u32 {self.mipmaps }
 
     pub fn depth(&self) -> u32 {
         self.depth
     }
 
-    #[allow(dead_code)]
-    pub fn depth_x(&self) -> Option<u32> {
-        self.depth_divisor
+    /// Array layers or cube faces. A 3d texture is a single layer.
+    pub fn layers(&self) -> u32 {
+        if self.kind == TextureKind::D3 { 1 } else { self.depth }
+    }
+
+    fn volume_depth(&self) -> u32 {
+        if self.kind == TextureKind::D3 { self.depth } else { 1 }
     }
 
-    #[allow(dead_code)]
-    pub fn depth_y(&self) -> Option<u32> {
-        self.depth_divisor.map(|dd|(self.depth / dd))
+    pub fn depth_x(&self) -> Option<u32> {
+        self.depth_divisor
     }
 
     fn layer_size(&self) -> u32 {
-        Self::size_per_layer(self.width(), self.height(), self.mipmaps())
+        Self::size_per_layer(self.width(), self.height(), self.volume_depth(), self.mipmaps())
     }
 
-    #[allow(dead_code)]
     pub fn get_layer_mut(&mut self, layer: u32) -> &mut [T]{
-        std::debug_assert!(layer < self.depth());
+        std::debug_assert!(layer < self.layers());
         let layer_size = self.layer_size();
         &mut self.pixels[(layer * layer_size) as usize..((layer + 1) * layer_size) as usize]
     }
 
-    #[allow(dead_code)]
     pub fn get_layer(&self, layer: u32) -> &[T]{
-        std::debug_assert!(layer < self.depth());
+        std::debug_assert!(layer < self.layers());
         let layer_size = self.layer_size();
         &self.pixels[(layer * layer_size) as usize..((layer + 1) * layer_size) as usize]
     }
 
-    #[allow(dead_code)]
-    pub fn get_mipmap_mut(&mut self, layer: u32, mipmap: u32) -> &mut [T]{
+    fn mipmap_range(&self, mipmap: u32) -> std::ops::Range<usize> {
         std::debug_assert!(mipmap < self.mipmaps());
-        let start = Self::size_per_layer(self.width(), self.height(), mipmap + 0) as usize;
-        let end   = Self::size_per_layer(self.width(), self.height(), mipmap + 1) as usize;
-        &mut self.get_layer_mut(layer)[start..end]
+        let start = Self::size_per_layer(self.width(), self.height(), self.volume_depth(), mipmap + 0) as usize;
+        let end   = Self::size_per_layer(self.width(), self.height(), self.volume_depth(), mipmap + 1) as usize;
+        start..end
+    }
+
+    /// For 3d textures this is the whole volume of the level, slice after slice.
+    pub fn get_mipmap_mut(&mut self, layer: u32, mipmap: u32) -> &mut [T]{
+        let range = self.mipmap_range(mipmap);
+        &mut self.get_layer_mut(layer)[range]
     }
 
-    #[allow(dead_code)]
     pub fn get_mipmap(&self, layer: u32, mipmap: u32) -> &[T]{
-        std::debug_assert!(mipmap < self.mipmaps());
-        let start = Self::size_per_layer(self.width(), self.height(), mipmap + 0) as usize;
-        let end   = Self::size_per_layer(self.width(), self.height(), mipmap + 1) as usize;
-        &self.get_layer(layer)[start..end]
+        let range = self.mipmap_range(mipmap);
+        &self.get_layer(layer)[range]
     }
 
-    #[allow(dead_code)]
     pub fn get_pixel_mut(&mut self, x: u32, y: u32, layer:u32, mipmap: u32) -> &mut T {
         std::debug_assert!(x < self.mipmapped_width(mipmap) && y < self.mipmapped_height(mipmap));
         let index = (x + y * self.mipmapped_width(mipmap)) as usize;
         &mut self.get_mipmap_mut(layer, mipmap)[index]
     }
 
-    #[allow(dead_code)]
-    pub fn get_pixel(&self, x: u32, y: u32, layer:u32, mipmap: u32) -> &T {
-        std::debug_assert!(x < self.mipmapped_width(mipmap) && y < self.mipmapped_height(mipmap));
-        let index = (x + y * self.mipmapped_width(mipmap)) as usize;
-        &self.get_mipmap(layer, mipmap)[index]
+    fn voxel_index(&self, x: u32, y: u32, z: u32, mipmap: u32) -> usize {
+        std::debug_assert!(x < self.mipmapped_width(mipmap) && y < self.mipmapped_height(mipmap) && z < self.mipmapped_depth(mipmap));
+        (x + (y + z * self.mipmapped_height(mipmap)) * self.mipmapped_width(mipmap)) as usize
+    }
+
+    fn dimension(&self) -> wgpu::TextureDimension {
+        match self.kind {
+            TextureKind::D1 => wgpu::TextureDimension::D1,
+            TextureKind::D2 | TextureKind::D2Array | TextureKind::Cube => wgpu::TextureDimension::D2,
+            TextureKind::D3 => wgpu::TextureDimension::D3
+        }
+    }
+
+    pub fn view_dimension(&self) -> wgpu::TextureViewDimension {
+        match self.kind {
+            TextureKind::D1 => wgpu::TextureViewDimension::D1,
+            TextureKind::D2 => wgpu::TextureViewDimension::D2,
+            TextureKind::D2Array => wgpu::TextureViewDimension::D2Array,
+            TextureKind::Cube => wgpu::TextureViewDimension::Cube,
+            TextureKind::D3 => wgpu::TextureViewDimension::D3
+        }
+    }
+
+    /// A view of the whole texture that matches the kind, e.g. a cube view for the six layers of a cube map.
+    pub fn create_view(&self, texture: &wgpu::Texture) -> wgpu::TextureView {
+        texture.create_view(&wgpu::TextureViewDescriptor {
+            dimension: Some(self.view_dimension()),
+            ..Default::default()
+        })
+    }
+
+    /// Filterable formats are downsampled level by level in linear space, with premultiplied alpha if they have
+    /// any. Every level is computed from the unquantized previous one; odd sizes are handled by the filter footprint.
+    /// The levels of integer formats take the top left texel of their footprint instead.
+    /// 3d textures are filtered along the depth as well.
+    pub fn generate_mipmaps(&mut self, filter: MipFilter){
+        for layer in 0..self.layers() {
+            if !T::FILTERABLE {
+                for mipmap in 1..self.mipmaps() {
+                    for z in 0..self.mipmapped_depth(mipmap) {
+                        for y in 0..self.mipmapped_height(mipmap){
+                            for x in 0..self.mipmapped_width(mipmap){
+                                let source = self.voxel_index(2 * x, 2 * y, (2 * z).min(self.mipmapped_depth(mipmap - 1) - 1), mipmap - 1);
+                                let target = self.voxel_index(x, y, z, mipmap);
+                                self.get_mipmap_mut(layer, mipmap)[target] = self.get_mipmap(layer, mipmap - 1)[source];
+                            }
+                        }
+                    }
+                }
+                continue;
+            }
+            let slice_size = (self.width() * self.height()) as usize;
+            let mut previous = self.get_mipmap(layer, 0)
+                .chunks(slice_size)
+                .map(|slice| LinearImage::from_pixels(self.width(), self.height(), slice))
+                .collect::<Vec<_>>();
+            for mipmap in 1..self.mipmaps() {
+                let slices = previous.iter()
+                    .map(|slice| slice.resample(self.mipmapped_width(mipmap), self.mipmapped_height(mipmap), filter))
+                    .collect::<Vec<_>>();
+                let current = resample_depth(&slices, self.mipmapped_depth(mipmap), filter);
+                let pixels = current.iter().flat_map(|slice| slice.to_pixels::<T>()).collect::<Vec<_>>();
+                self.get_mipmap_mut(layer, mipmap).copy_from_slice(&pixels);
+                previous = current;
+            }
+        }
     }
 
     pub fn as_bytes(&self) -> &[u8] {
         bytemuck::cast_slice(&self.pixels)
     }
 
-    pub fn to_texture(&self, device: &wgpu::Device, queue: &wgpu::Queue, format: wgpu::TextureFormat, usage: wgpu::TextureUsage) -> wgpu::Texture {
-        device.create_texture_with_data(queue, &wgpu::TextureDescriptor {
+    pub fn as_bytes_mut(&mut self) -> &mut [u8] {
+        bytemuck::cast_slice_mut(&mut self.pixels)
+    }
+
+    /// The number of layers per row of the image the layers were cut from.
+    pub fn set_depth_divisor(&mut self, depth_divisor: Option<u32>) {
+        self.depth_divisor = depth_divisor;
+    }
+
+    /// Fails if the pixels can't be interpreted as `format`.
+    pub fn to_texture(&self, device: &wgpu::Device, queue: &wgpu::Queue, format: wgpu::TextureFormat, usage: wgpu::TextureUsage) -> Result<wgpu::Texture, Error> {
+        ensure!(T::is_compatible(format), "Texture data of {:?} can't be uploaded as {:?}", T::FORMAT, format);
+        Ok(device.create_texture_with_data(queue, &wgpu::TextureDescriptor {
             size: Extent3d {
                 width: self.width(),
                 height: self.height(),
@@ -192,11 +321,57 @@ impl<T> TextureData<T> where T : bytemuck::Pod{
             },
             mip_level_count: self.mipmaps(),
             sample_count: 1,
-            dimension: wgpu::TextureDimension::D2,
+            dimension: self.dimension(),
             format,
             usage,
             label: Some("tile_set_texture"),
-        }, self.as_bytes())
+        }, self.as_bytes()))
+    }
+
+    /// Uploads only the first level of every layer and lets `generator` compute the rest of the chain.
+    pub fn to_texture_with_gpu_mipmaps(&self, device: &wgpu::Device, queue: &wgpu::Queue, usage: wgpu::TextureUsage, generator: &MipGenerator) -> Result<wgpu::Texture, Error> {
+        ensure!(T::FILTERABLE && T::is_compatible(generator.format()), "Texture data of {:?} can't be mipmapped as {:?}", T::FORMAT, generator.format());
+        ensure!(self.dimension() == wgpu::TextureDimension::D2, "Only 2d textures can be mipmapped on the gpu");
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            size: Extent3d {
+                width: self.width(),
+                height: self.height(),
+                depth: self.depth()
+            },
+            mip_level_count: self.mipmaps(),
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: generator.format(),
+            usage: usage | wgpu::TextureUsage::COPY_DST | wgpu::TextureUsage::SAMPLED | wgpu::TextureUsage::RENDER_ATTACHMENT,
+            label: Some("tile_set_texture"),
+        });
+
+        for layer in 0..self.layers() {
+            queue.write_texture(
+                wgpu::TextureCopyView {
+                    texture: &texture,
+                    mip_level: 0,
+                    origin: wgpu::Origin3d { x: 0, y: 0, z: layer }
+                },
+                bytemuck::cast_slice(self.get_mipmap(layer, 0)),
+                wgpu::TextureDataLayout {
+                    offset: 0,
+                    bytes_per_row: self.width() * std::mem::size_of::<T>() as u32,
+                    rows_per_image: self.height()
+                },
+                Extent3d {
+                    width: self.width(),
+                    height: self.height(),
+                    depth: 1
+                });
+        }
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Mip Encoder"),
+        });
+        generator.generate(device, &mut encoder, &texture, self.layers(), self.mipmaps());
+        queue.submit(std::iter::once(encoder.finish()));
+        Ok(texture)
     }
 
 }
\ No newline at end of file
@@ -0,0 +1,17 @@
+// Shared by the shaders that sample the tileset. Has to match TILE_EXTRUSION in texture.rs
+const float TILE_EXTRUSION = 2.0;
+
+// Maps coordinates inside a tile to the layer, skipping the extruded border around it
+vec2 tile_uv(vec2 tile_coords, vec2 layer_size) {
+    return (TILE_EXTRUSION + tile_coords * (layer_size - 2.0 * TILE_EXTRUSION)) / layer_size;
+}
+
+// Keeps texels sharp when magnified while still blending across their edges, dx and dy are the
+// derivatives of uv
+vec2 pixel_art_uv(vec2 uv, vec2 layer_size, vec2 dx, vec2 dy) {
+    vec2 texel = uv * layer_size;
+    vec2 width = max((abs(dx) + abs(dy)) * layer_size, vec2(1e-5));
+    vec2 seam = floor(texel + 0.5);
+    texel = seam + clamp((texel - seam) / width, -0.5, 0.5);
+    return texel / layer_size;
+}
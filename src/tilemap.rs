@@ -0,0 +1,121 @@
+use std::collections::HashSet;
+use glam::*;
+use serde::Deserialize;
+use crate::catalog::Catalog;
+use crate::physics::Aabb;
+
+/// Gameplay properties of the tiles of a tileset, keyed by the tileset label in the project.
+#[derive(Deserialize, Clone, Debug, Default)]
+pub struct TileProperties {
+    /// Tiles that kill the player on touch. They are not solid so the player sinks into them.
+    #[serde(default)]
+    pub hazards: Vec<u32>,
+    /// Tiles that cycle through other tiles of the tileset. Collisions only look at the placed tile.
+    #[serde(default)]
+    pub animations: Vec<TileAnimation>
+}
+
+/// Replaces a placed tile with each of `frames` in turn, showing every frame for `frame_duration` seconds.
+#[derive(Deserialize, Clone, Debug)]
+pub struct TileAnimation {
+    pub tile: u32,
+    pub frames: Vec<u32>,
+    pub frame_duration: f32
+}
+
+pub type TilesetProperties = Catalog<TileProperties>;
+
+/// Transform bits of a tile, only read by the shader: 1 mirrors it horizontally, 2 vertically and 4 turns it
+/// a quarter clockwise. The tile is rotated first and mirrored afterwards.
+pub const TRANSFORM_BITS: u8 = 0b111;
+
+#[derive(Clone)]
+pub struct TileGrid {
+    width: u32,
+    height: u32,
+    tiles: Box<[Option<u32>]>,
+    /// Only changes how the tiles look, collisions ignore it.
+    transforms: Box<[u8]>,
+    hazards: HashSet<u32>
+}
+
+impl TileGrid {
+
+    pub fn new(width: u32, height: u32) -> Self {
+        Self {
+            width,
+            height,
+            tiles: vec![None; (width * height) as usize].into_boxed_slice(),
+            transforms: vec![0; (width * height) as usize].into_boxed_slice(),
+            hazards: HashSet::new()
+        }
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    fn index(&self, x: i32, y: i32) -> Option<usize> {
+        if (0..self.width as i32).contains(&x) && (0..self.height as i32).contains(&y) {
+            Some((x + y * self.width as i32) as usize)
+        } else {
+            None
+        }
+    }
+
+    /// Tile coordinates are in world space, so `y = 0` is the bottom row of the level.
+    pub fn get(&self, x: i32, y: i32) -> Option<u32> {
+        self.index(x, y).and_then(|i| self.tiles[i])
+    }
+
+    pub fn set(&mut self, x: i32, y: i32, tile: Option<u32>) {
+        if let Some(i) = self.index(x, y) {
+            self.tiles[i] = tile;
+        }
+    }
+
+    pub fn transform(&self, x: i32, y: i32) -> u8 {
+        self.index(x, y).map_or(0, |i| self.transforms[i])
+    }
+
+    pub fn set_transform(&mut self, x: i32, y: i32, transform: u8) {
+        if let Some(i) = self.index(x, y) {
+            self.transforms[i] = transform & TRANSFORM_BITS;
+        }
+    }
+
+    pub fn set_properties(&mut self, properties: &TileProperties) {
+        self.hazards = properties.hazards.iter().copied().collect();
+    }
+
+    /// The sides of the level count as walls, everything above and below is open.
+    pub fn is_solid(&self, x: i32, y: i32) -> bool {
+        if !(0..self.width as i32).contains(&x) {
+            return (0..self.height as i32).contains(&y);
+        }
+        matches!(self.get(x, y), Some(id) if !self.hazards.contains(&id))
+    }
+
+    pub fn is_hazard(&self, x: i32, y: i32) -> bool {
+        matches!(self.get(x, y), Some(id) if self.hazards.contains(&id))
+    }
+
+    pub fn touches_hazard(&self, aabb: &Aabb) -> bool {
+        let min = aabb.min.floor();
+        let max = aabb.max.ceil();
+        (min.y as i32..max.y as i32).any(|y| (min.x as i32..max.x as i32).any(|x| self.is_hazard(x, y)))
+    }
+
+    pub fn is_solid_at(&self, point: Vec2) -> bool {
+        self.is_solid(point.x.floor() as i32, point.y.floor() as i32)
+    }
+
+    pub fn size(&self) -> Vec2 {
+        vec2(self.width as f32, self.height as f32)
+    }
+
+}
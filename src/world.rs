@@ -0,0 +1,358 @@
+use glam::*;
+use anyhow::*;
+use crate::enemy::{Archetypes, Enemy, Interaction};
+use crate::collectible::{Collectible, CollectibleKinds};
+use crate::checkpoint::Checkpoint;
+use crate::input::Input;
+use crate::level::LevelData;
+use crate::player::Player;
+use crate::physics::Aabb;
+use crate::platform::{carry, land, Platform, PlatformKinds};
+use crate::sprite::{Sprite, SpriteBatch};
+use crate::tilemap::{TileGrid, TileProperties};
+
+const PLAYER_SPAWN: &str = "player";
+const CHECKPOINT: &str = "checkpoint";
+const GOAL: &str = "goal";
+const GOAL_SIZE: Vec2 = const_vec2!([0.8, 1.6]);
+const GOAL_COLOR: [f32; 4] = [1.0, 1.0, 1.0, 1.0];
+const START_LIVES: u32 = 3;
+const EXTRA_LIFE_SCORE: u32 = 100;
+
+/// How far the player may leave the level before dying.
+const OUT_OF_BOUNDS_MARGIN: f32 = 2.0;
+const DEATH_BOUNCE: f32 = 14.0;
+const DEATH_TIME: f32 = 1.2;
+const FADE_OUT_TIME: f32 = 0.5;
+const FADE_IN_TIME: f32 = 0.4;
+const COMPLETION_TIME: f32 = 1.5;
+
+pub const PLAYER_COLOR: [f32; 4] = [0.95, 0.8, 0.3, 1.0];
+const DEAD_PLAYER_COLOR: [f32; 4] = [0.8, 0.3, 0.25, 1.0];
+
+pub struct Definitions {
+    pub enemies: Archetypes,
+    pub platforms: PlatformKinds,
+    pub collectibles: CollectibleKinds,
+    pub tiles: TileProperties
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Phase {
+    Playing,
+    Dying(f32),
+    Respawning(f32),
+    Completed(f32)
+}
+
+pub struct Completion {
+    /// Seconds since the level was started, including deaths.
+    pub time: f32,
+    /// Indices of the collectibles collected in this run.
+    pub collected: Vec<usize>
+}
+
+/// The state the level returns to when the player respawns.
+struct Snapshot {
+    spawn: Vec2,
+    score: u32,
+    collected: Vec<bool>
+}
+
+/// Everything that moves inside a level.
+pub struct World {
+    pub grid: TileGrid,
+    pub player: Player,
+    pub enemies: Vec<Enemy>,
+    pub platforms: Vec<Platform>,
+    pub collectibles: Vec<Collectible>,
+    pub checkpoints: Vec<Checkpoint>,
+    pub goal: Option<Aabb>,
+    pub score: u32,
+    pub lives: u32,
+    /// Multiples of `EXTRA_LIFE_SCORE` that already paid out an extra life in this run, so a score
+    /// that is lost on respawn and earned again doesn't pay twice.
+    extra_lives_awarded: u32,
+    initial_enemies: Vec<Enemy>,
+    initial_platforms: Vec<Platform>,
+    start: Vec2,
+    snapshot: Snapshot,
+    phase: Phase,
+    completion: Option<Completion>,
+    level_time: f32,
+    time: f32
+}
+
+impl World {
+
+    pub fn new(level: &LevelData, definitions: &Definitions) -> Result<Self, Error> {
+        let start = level.spawns_named(PLAYER_SPAWN)
+            .next()
+            .context("The level has no player spawn")?
+            .position;
+
+        let mut enemies = Vec::new();
+        let mut platforms = Vec::new();
+        let mut collectibles = Vec::new();
+        let mut checkpoints = Vec::new();
+        let mut goal = None;
+        for spawn in &level.spawns {
+            if let Some(archetype) = definitions.enemies.get(&spawn.name) {
+                enemies.push(Enemy::new(archetype, spawn.position));
+            } else if let Some(kind) = definitions.platforms.get(&spawn.name) {
+                platforms.push(Platform::new(kind, spawn.position, &spawn.nodes));
+            } else if let Some(kind) = definitions.collectibles.get(&spawn.name) {
+                collectibles.push(Collectible::new(kind, spawn.position));
+            } else if spawn.name == CHECKPOINT {
+                checkpoints.push(Checkpoint::new(spawn.position));
+            } else if spawn.name == GOAL {
+                goal = Some(Aabb::new(spawn.position - vec2(GOAL_SIZE.x * 0.5, 0.0), GOAL_SIZE));
+            } else if spawn.name != PLAYER_SPAWN {
+                log::warn!("Unknown entity: {}", spawn.name);
+            }
+        }
+
+        let mut grid = level.grid.clone();
+        grid.set_properties(&definitions.tiles);
+
+        Ok(Self {
+            grid,
+            player: Player::new(start),
+            initial_enemies: enemies.clone(),
+            initial_platforms: platforms.clone(),
+            snapshot: Snapshot {
+                spawn: start,
+                score: 0,
+                collected: vec![false; collectibles.len()]
+            },
+            enemies,
+            platforms,
+            collectibles,
+            checkpoints,
+            goal,
+            score: 0,
+            lives: START_LIVES,
+            extra_lives_awarded: 0,
+            start,
+            phase: Phase::Playing,
+            completion: None,
+            level_time: 0.0,
+            time: 0.0
+        })
+    }
+
+    pub fn update(&mut self, input: &Input, dt: f32) {
+        self.time += dt;
+        if !matches!(self.phase, Phase::Completed(_)) {
+            self.level_time += dt;
+        }
+
+        self.update_platforms(dt);
+
+        match self.phase {
+            Phase::Playing => {
+                self.update_player(input, dt);
+                if self.player_should_die() {
+                    self.die();
+                } else if self.goal.map_or(false, |goal| goal.overlaps(&self.player.body.aabb())) {
+                    self.complete();
+                }
+            }
+            Phase::Dying(elapsed) => {
+                self.player.body.apply_gravity(dt);
+                self.player.body.position += self.player.body.velocity * dt;
+                self.phase = if elapsed + dt >= DEATH_TIME {
+                    self.respawn();
+                    Phase::Respawning(0.0)
+                } else {
+                    Phase::Dying(elapsed + dt)
+                };
+            }
+            Phase::Respawning(elapsed) => {
+                self.update_player(input, dt);
+                self.phase = if elapsed + dt >= FADE_IN_TIME { Phase::Playing } else { Phase::Respawning(elapsed + dt) };
+                if self.player_should_die() {
+                    self.die();
+                }
+            }
+            Phase::Completed(elapsed) => {
+                self.phase = if elapsed + dt >= COMPLETION_TIME {
+                    self.restart();
+                    self.respawn();
+                    Phase::Respawning(0.0)
+                } else {
+                    Phase::Completed(elapsed + dt)
+                };
+            }
+        }
+
+        self.update_enemies(dt);
+    }
+
+    fn update_platforms(&mut self, dt: f32) {
+        let player_frozen = matches!(self.phase, Phase::Dying(_) | Phase::Completed(_));
+        for platform in &mut self.platforms {
+            let before = platform.aabb();
+            let delta = platform.update(dt);
+            if !player_frozen {
+                carry(&before, delta, &mut self.player.body, &self.grid);
+            }
+            for enemy in self.enemies.iter_mut().filter(|enemy| enemy.alive) {
+                carry(&before, delta, &mut enemy.body, &self.grid);
+            }
+        }
+    }
+
+    fn update_player(&mut self, input: &Input, dt: f32) {
+        let previous_bottom = self.player.body.position.y;
+        self.player.update(input, &self.grid, dt);
+        land(&mut self.player.body, previous_bottom, &self.platforms);
+
+        let player = self.player.body.aabb();
+        let collected: u32 = self.collectibles
+            .iter_mut()
+            .filter_map(|collectible| collectible.collect(&player))
+            .sum();
+        self.add_score(collected);
+
+        if let Some(checkpoint) = self.checkpoints.iter_mut().find(|checkpoint| !checkpoint.active && checkpoint.aabb().overlaps(&player)) {
+            checkpoint.active = true;
+            self.snapshot = Snapshot {
+                spawn: checkpoint.spawn,
+                score: self.score,
+                collected: self.collectibles.iter().map(|collectible| collectible.collected).collect()
+            };
+        }
+    }
+
+    fn update_enemies(&mut self, dt: f32) {
+        let interactive = self.phase == Phase::Playing;
+        let mut stomped = 0;
+        for enemy in &mut self.enemies {
+            let previous_bottom = enemy.body.position.y;
+            enemy.update(&self.grid, &self.player, dt);
+            if !enemy.is_flying() {
+                land(&mut enemy.body, previous_bottom, &self.platforms);
+            }
+            if interactive && enemy.interact(&mut self.player) == Interaction::Stomped {
+                stomped += enemy.archetype.score;
+            }
+        }
+        self.add_score(stomped);
+    }
+
+    fn player_should_die(&self) -> bool {
+        let aabb = self.player.body.aabb();
+        let level = self.grid.size();
+        let out_of_bounds = aabb.max.y < -OUT_OF_BOUNDS_MARGIN ||
+            aabb.max.x < -OUT_OF_BOUNDS_MARGIN ||
+            aabb.min.x > level.x + OUT_OF_BOUNDS_MARGIN;
+        self.player.health == 0 || out_of_bounds || self.grid.touches_hazard(&aabb)
+    }
+
+    fn die(&mut self) {
+        self.player.body.velocity = vec2(0.0, DEATH_BOUNCE);
+        self.lives = self.lives.saturating_sub(1);
+        self.phase = Phase::Dying(0.0);
+    }
+
+    fn complete(&mut self) {
+        self.completion = Some(Completion {
+            time: self.level_time,
+            collected: self.collectibles
+                .iter()
+                .enumerate()
+                .filter(|(_, collectible)| collectible.collected)
+                .map(|(i, _)| i)
+                .collect()
+        });
+        self.phase = Phase::Completed(0.0);
+    }
+
+    /// Returns the result of the run once the player reached the goal.
+    pub fn take_completion(&mut self) -> Option<Completion> {
+        self.completion.take()
+    }
+
+    /// Forgets all progress so the next respawn starts the level from scratch.
+    fn restart(&mut self) {
+        self.lives = START_LIVES;
+        self.extra_lives_awarded = 0;
+        self.level_time = 0.0;
+        self.snapshot = Snapshot {
+            spawn: self.start,
+            score: 0,
+            collected: vec![false; self.collectibles.len()]
+        };
+        for checkpoint in &mut self.checkpoints {
+            checkpoint.active = false;
+        }
+    }
+
+    /// Puts the player back at the last checkpoint and resets enemies, platforms and collectibles to match it.
+    /// Running out of lives restarts the level from scratch.
+    fn respawn(&mut self) {
+        if self.lives == 0 {
+            self.restart();
+        }
+        self.player = Player::new(self.snapshot.spawn);
+        self.score = self.snapshot.score;
+        for (collectible, collected) in self.collectibles.iter_mut().zip(&self.snapshot.collected) {
+            collectible.collected = *collected;
+        }
+        self.enemies = self.initial_enemies.clone();
+        self.platforms = self.initial_platforms.clone();
+    }
+
+    /// Adds to the score and awards an extra life the first time it passes each multiple of `EXTRA_LIFE_SCORE`.
+    fn add_score(&mut self, amount: u32) {
+        self.score += amount;
+        let earned = self.score / EXTRA_LIFE_SCORE;
+        if earned > self.extra_lives_awarded {
+            self.lives += earned - self.extra_lives_awarded;
+            self.extra_lives_awarded = earned;
+        }
+    }
+
+    /// How much the screen is faded to black, from 0 to 1.
+    pub fn fade(&self) -> f32 {
+        match self.phase {
+            Phase::Playing => 0.0,
+            Phase::Dying(elapsed) => ((elapsed - (DEATH_TIME - FADE_OUT_TIME)) / FADE_OUT_TIME).max(0.0).min(1.0),
+            Phase::Respawning(elapsed) => 1.0 - (elapsed / FADE_IN_TIME).min(1.0),
+            Phase::Completed(elapsed) => ((elapsed - (COMPLETION_TIME - FADE_OUT_TIME)) / FADE_OUT_TIME).max(0.0).min(1.0)
+        }
+    }
+
+    pub fn sprites(&self, batch: &mut SpriteBatch) {
+        batch.extend(self.checkpoints
+            .iter()
+            .map(|checkpoint| {
+                let aabb = checkpoint.aabb();
+                Sprite::rect(aabb.min, aabb.max - aabb.min, checkpoint.color())
+            }));
+        if let Some(goal) = self.goal {
+            batch.push(Sprite::rect(goal.min, goal.max - goal.min, GOAL_COLOR));
+        }
+        batch.extend(self.platforms
+            .iter()
+            .map(|platform| Sprite::tile(platform.position, Vec2::from(platform.kind.size), platform.kind.tile)
+                .with_color(platform.kind.color)));
+        batch.extend(self.collectibles
+            .iter()
+            .filter(|collectible| !collectible.collected)
+            .map(|collectible| Sprite::tile(collectible.draw_position(self.time), Vec2::from(collectible.kind.size), collectible.kind.tile)
+                .with_color(collectible.kind.color)));
+        batch.extend(self.enemies
+            .iter()
+            .filter(|enemy| enemy.alive)
+            .map(|enemy| Sprite::tile(enemy.body.position, enemy.body.size, enemy.archetype.tile)
+                .with_color(enemy.archetype.color)));
+        match self.phase {
+            Phase::Dying(_) => batch.push(Sprite::rect(self.player.body.position, self.player.body.size, DEAD_PLAYER_COLOR)),
+            _ if self.player.is_visible(self.time) => batch.push(Sprite::rect(self.player.body.position, self.player.body.size, PLAYER_COLOR)),
+            _ => {}
+        }
+    }
+
+}